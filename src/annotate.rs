@@ -0,0 +1,194 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::capture::Burst;
+use crate::pcap::{to_u16, to_u32, Format, RecordKind, RecordReader};
+
+/// Bytes read from the pcapng file at a time.
+const READ_SIZE: usize = 1 << 20;
+
+/// Option code of a comment in a pcapng block.
+const OPT_COMMENT: u16 = 1;
+
+/// Which bursts the packets written by tshark belong to, for adding a comment with the
+/// flow and burst to each packet of the written pcapng file.
+///
+/// Packets are identified by their index in the written file, which is the same as the
+/// index of their line from tshark, as tshark writes and prints the same packets.
+///
+/// Everything is kept in memory until the file is annotated: a slot of 8 bytes for every
+/// packet of the capture up to the last one in a burst, and a comment for every burst.
+/// This grows without bound on a long live capture.
+#[derive(Debug, Default)]
+pub struct Annotations {
+    state: Mutex<AnnotationState>,
+}
+
+#[derive(Debug, Default)]
+struct AnnotationState {
+    /// Index into comments of each packet's burst, if any.
+    packets: Vec<Option<u32>>,
+    comments: Vec<String>,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the packets of a burst, the numbered burst of a numbered flow.
+    pub fn add_burst(&self, flow_id: u32, burst_id: u32, burst: &Burst, packets: &[u64]) {
+        let comment = format!(
+            "burstshark flow {} burst {}: {}:{} -> {}:{}, {} packets, {} bytes, start {:.9}",
+            flow_id,
+            burst_id,
+            burst.src,
+            burst.src_port,
+            burst.dst,
+            burst.dst_port,
+            burst.num_packets,
+            burst.size,
+            burst.start,
+        );
+
+        let mut state = self.state.lock().unwrap();
+        let comment_index = state.comments.len() as u32;
+        state.comments.push(comment);
+
+        for &packet in packets {
+            let packet = packet as usize;
+            if packet >= state.packets.len() {
+                state.packets.resize(packet + 1, None);
+            }
+            state.packets[packet] = Some(comment_index);
+        }
+    }
+
+    /// Rewrite the pcapng file at path with a comment on each packet of a burst. Returns
+    /// the number of packets annotated.
+    pub fn write(&self, path: &str) -> Result<usize, Box<dyn Error>> {
+        let state = self.state.lock().unwrap();
+
+        let tmp = format!("{}.tmp", path);
+        let annotated = rewrite(Path::new(path), Path::new(&tmp), &state).inspect_err(|_| {
+            std::fs::remove_file(&tmp).ok();
+        })?;
+        std::fs::rename(&tmp, path)?;
+
+        Ok(annotated)
+    }
+}
+
+fn rewrite(path: &Path, tmp: &Path, state: &AnnotationState) -> Result<usize, Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let mut out = BufWriter::new(File::create(tmp)?);
+    let mut reader = RecordReader::new();
+    let mut buf = vec![0; READ_SIZE];
+
+    let mut little_endian = true;
+    let mut index = 0;
+    let mut annotated = 0;
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        reader.push(&buf[..n]);
+
+        while let Some(record) = reader.next_record()? {
+            if reader.format() != Some(Format::Pcapng) {
+                return Err("not a pcapng file".into());
+            }
+
+            let mut data = record.data;
+            match record.kind {
+                RecordKind::SectionHeader => {
+                    little_endian = data[8..12] == [0x4d, 0x3c, 0x2b, 0x1a]
+                }
+                RecordKind::Packet => {
+                    let comment = state.packets.get(index).copied().flatten();
+                    if let Some(comment) = comment {
+                        let comment = &state.comments[comment as usize];
+                        if let Some(with_comment) = add_comment(&data, comment, little_endian) {
+                            data = with_comment;
+                            annotated += 1;
+                        }
+                    }
+                    index += 1;
+                }
+                RecordKind::FileHeader | RecordKind::Other => {}
+            }
+
+            out.write_all(&data)?;
+        }
+    }
+
+    out.flush()?;
+    Ok(annotated)
+}
+
+/// A copy of an enhanced or obsolete packet block with a comment added to its options.
+/// None for simple packet blocks, which have no options.
+fn add_comment(block: &[u8], comment: &str, little_endian: bool) -> Option<Vec<u8>> {
+    let u16_bytes = |value: u16| match little_endian {
+        true => value.to_le_bytes(),
+        false => value.to_be_bytes(),
+    };
+    let u32_bytes = |value: u32| match little_endian {
+        true => value.to_le_bytes(),
+        false => value.to_be_bytes(),
+    };
+    let read_u16 = |bytes: &[u8]| to_u16(bytes, little_endian);
+    let read_u32 = |bytes: &[u8]| to_u32(bytes, little_endian);
+
+    // Options follow the captured packet data, padded to 32 bits.
+    let block_type = read_u32(&block[0..4]);
+    let captured_len = match block_type {
+        2 | 6 => read_u32(block.get(20..24)?) as usize,
+        _ => return None,
+    };
+    let options_start = 28 + captured_len.next_multiple_of(4);
+    let options_end = block.len() - 4;
+    if options_start > options_end {
+        return None;
+    }
+
+    // Existing options without the end of options marker, which is added again last.
+    let mut options = Vec::new();
+    let mut rest = &block[options_start..options_end];
+    while rest.len() >= 4 {
+        let code = read_u16(&rest[0..2]);
+        let len = read_u16(&rest[2..4]) as usize;
+        if code == 0 {
+            break;
+        }
+        let option_len = (4 + len.next_multiple_of(4)).min(rest.len());
+        options.extend_from_slice(&rest[..option_len]);
+        rest = &rest[option_len..];
+    }
+
+    // Comments longer than an option can hold are truncated at a character boundary.
+    let mut comment_len = comment.len().min(u16::MAX as usize - 3);
+    while !comment.is_char_boundary(comment_len) {
+        comment_len -= 1;
+    }
+    options.extend_from_slice(&u16_bytes(OPT_COMMENT));
+    options.extend_from_slice(&u16_bytes(comment_len as u16));
+    options.extend_from_slice(&comment.as_bytes()[..comment_len]);
+    options.resize(options.len().next_multiple_of(4), 0);
+    options.extend_from_slice(&[0; 4]);
+
+    let block_len = options_start + options.len() + 4;
+    let mut data = Vec::with_capacity(block_len);
+    data.extend_from_slice(&block[0..4]);
+    data.extend_from_slice(&u32_bytes(block_len as u32));
+    data.extend_from_slice(&block[8..options_start]);
+    data.extend_from_slice(&options);
+    data.extend_from_slice(&u32_bytes(block_len as u32));
+
+    Some(data)
+}
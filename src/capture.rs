@@ -1,18 +1,25 @@
-use std::collections::{hash_map::Entry, HashMap};
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
 use std::error::Error;
-use std::process::Stdio;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
-use nix::sys::signal::{kill, Signal};
-use nix::unistd::Pid;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
-use tokio::sync::mpsc;
-use tokio::time::{sleep, Duration};
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep, sleep_until, Duration, Instant};
+
+use crate::annotate::Annotations;
+use crate::metrics::Metrics;
+use crate::source::{Clock, Input, Source};
 
 const FLOW_TIMEOUT: f64 = 30.0;
 
-type FlowKey = (String, String, u16, u16);
+/// Source and destination addresses and ports of a flow, and the probe and interface it
+/// was captured on.
+pub type FlowKey = (String, String, u16, u16, Option<String>, Option<String>);
 
+/// A burst of a flow. Serialized with its duration and rate in addition to its fields.
 #[derive(Debug, Clone)]
 pub struct Burst {
     pub src: String,
@@ -23,13 +30,123 @@ pub struct Burst {
     pub end: f64,
     pub num_packets: u16,
     pub size: u32,
+    /// Capture interface, if capturing on multiple interfaces.
+    pub interface: Option<String>,
+    /// ID of the remote probe that captured the burst, if collecting from probes.
+    pub probe: Option<String>,
+    /// Set for bursts of WLAN frames.
+    pub wlan: Option<WlanBurst>,
+}
+
+impl Serialize for Burst {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Burst", 13)?;
+        state.serialize_field("src", &self.src)?;
+        state.serialize_field("dst", &self.dst)?;
+        state.serialize_field("src_port", &self.src_port)?;
+        state.serialize_field("dst_port", &self.dst_port)?;
+        state.serialize_field("start", &self.start)?;
+        state.serialize_field("end", &self.end)?;
+        state.serialize_field("num_packets", &self.num_packets)?;
+        state.serialize_field("size", &self.size)?;
+        state.serialize_field("duration", &self.duration())?;
+        state.serialize_field("rate", &self.rate())?;
+        match &self.interface {
+            Some(interface) => state.serialize_field("interface", interface)?,
+            None => state.skip_field("interface")?,
+        }
+        match &self.probe {
+            Some(probe) => state.serialize_field("probe", probe)?,
+            None => state.skip_field("probe")?,
+        }
+        match &self.wlan {
+            Some(wlan) => state.serialize_field("wlan", wlan)?,
+            None => state.skip_field("wlan")?,
+        }
+        state.end()
+    }
+}
+
+/// WLAN addressing of a burst, from its first frame.
+#[derive(Debug, Clone, Serialize)]
+pub struct WlanBurst {
+    pub bssid: Option<String>,
+    pub transmitter: String,
+    pub receiver: String,
+    /// Direction relative to the station, from the To DS and From DS bits. None if both
+    /// or neither are set.
+    pub direction: Option<Direction>,
+    pub radio: RadioStats,
+    /// Number of frames missed by the capture whose sizes were estimated. Included in
+    /// num_packets.
+    pub num_estimated: u16,
+    /// Bytes of the estimated frames. Included in size.
+    pub estimated_size: u32,
+    /// Size of the MAC payloads, including protection and LLC/SNAP overhead that is
+    /// subtracted from size.
+    pub raw_size: u32,
+}
+
+/// Method of estimating the size of WLAN frames missed by the capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Estimator {
+    /// Size of the last frame before the missed frames.
+    Last,
+    /// Mean size of the frames before and after the missed frames.
+    Mean,
+    /// Median size of the flow's recent full-size frames.
+    Median,
+    /// Most common size of the flow's recent frames.
+    Mode,
+}
+
+/// Radio metadata of the frames captured in a burst. Fields are None if no frame had the
+/// information, e.g. without a radiotap header.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RadioStats {
+    /// Mean signal strength in dBm.
+    pub mean_signal: Option<f64>,
+    /// Minimum signal strength in dBm.
+    pub min_signal: Option<i32>,
+    /// Most common MCS index.
+    pub dominant_mcs: Option<u8>,
+    /// Mean data rate in Mbit/s.
+    pub mean_data_rate: Option<f64>,
+    pub channel: Option<u16>,
+    /// Fraction of captured frames with the retry bit set.
+    pub retry_fraction: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// From a station to the distribution system (To DS).
+    Uplink,
+    /// From the distribution system to a station (From DS).
+    Downlink,
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Uplink => write!(f, "uplink"),
+            Direction::Downlink => write!(f, "downlink"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct CommonOptions {
+    /// Arguments for tshark, excluding those of the source.
     pub tshark_args: Vec<String>,
+    pub source: Source,
     pub burst_timeout: f64,
+    /// The name of the capture interface is the last field of each packet.
+    pub tag_interfaces: bool,
     pub output_tx: mpsc::Sender<Burst>,
+    pub metrics: Arc<Metrics>,
+    /// Set to record the bursts of the packets written by tshark.
+    pub annotations: Option<Arc<Annotations>>,
 }
 
 #[derive(Debug, Clone)]
@@ -41,7 +158,12 @@ pub enum CaptureType {
     Wlan {
         opts: CommonOptions,
         no_estimation: bool,
+        estimator: Estimator,
         max_deviation: u16,
+        /// Protected frames use GCMP or CCMP-256 with a 16-byte MIC.
+        gcmp: bool,
+        /// Only frames of these BSSIDs are considered if not empty.
+        bssids: Vec<String>,
     },
 }
 
@@ -51,73 +173,184 @@ impl CaptureType {
             CaptureType::Ip { opts, .. } | CaptureType::Wlan { opts, .. } => opts,
         };
 
-        let mut tshark = Command::new("tshark")
-            .args(&opts.tshark_args)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .map_err(|err| format!("failed to start tshark: {}", err))?;
-
-        if let Some(tshark_pid) = tshark.id() {
-            let tshark_pid = tshark_pid as i32;
-            tokio::spawn(async move {
-                tokio::signal::ctrl_c().await.unwrap();
-                kill(Pid::from_raw(tshark_pid), Signal::SIGTERM).unwrap();
-            });
+        let (mut input_rx, source_handle) = opts.source.start(&opts.tshark_args);
+
+        let mut captures = Vec::new();
+        while let Some(input) = input_rx.recv().await {
+            let capture_type = self.clone();
+            let clock_mode = opts.source.clock();
+
+            captures.push(tokio::spawn(async move {
+                let probe = input.probe.clone();
+                match (capture_type.dispatch(input, clock_mode).await, probe) {
+                    // A failing probe does not stop the collection from other probes.
+                    (Err(err), Some(probe)) => {
+                        eprintln!("Probe {}: {}", probe, err);
+                        Ok(())
+                    }
+                    (result, _) => result.map_err(|err| err.to_string()),
+                }
+            }));
+        }
+
+        for capture in captures {
+            capture.await??;
         }
 
-        let stdout = tshark.stdout.take().unwrap();
-        let reader = BufReader::new(stdout);
-        let mut lines = reader.lines();
+        source_handle.await??;
+
+        Ok(())
+    }
+
+    /// Split the packets of an input into flows, each with a handler that outputs its
+    /// bursts.
+    async fn dispatch(&self, input: Input, clock_mode: Clock) -> Result<(), Box<dyn Error>> {
+        let opts = match self {
+            CaptureType::Ip { opts, .. } | CaptureType::Wlan { opts, .. } => opts,
+        };
+        let mut line_rx = input.lines;
+        let packet_counter = opts.metrics.packet_counter(input.probe.as_deref());
+
+        // Capture time, if bursts and flows do not time out by program time.
+        let (clock_tx, clock_rx) = watch::channel(f64::NEG_INFINITY);
+        let clock = (clock_mode != Clock::Wall).then_some(clock_rx);
+        // Flows are woken at most this often, in capture time, to check for timeouts.
+        let clock_tick = opts.burst_timeout / 2.0;
+        // Latest packet timestamp and when it was read.
+        let mut last_packet = (f64::NEG_INFINITY, Instant::now());
 
         let mut flows = HashMap::<FlowKey, mpsc::Sender<Packet>>::new();
+        // WLAN flows by the transmitter and receiver addresses of their frames.
+        let mut links = HashMap::<(String, String, Option<String>), HashSet<FlowKey>>::new();
         let (timeout_tx, mut timeout_rx) = mpsc::channel::<FlowKey>(100);
+        // Lines read from tshark, including those that fail to parse.
+        let mut num_lines = 0;
+        let mut num_flows = 0;
+        // Only the first packet that fails to parse is printed, the rest are counted.
+        let mut num_parse_errors = 0;
 
         loop {
             tokio::select! {
-                line = lines.next_line() => {
-                    match line? {
+                line = line_rx.recv() => {
+                    match line {
                         Some(line) => {
-                            let packet = Packet::from_tshark(&line, self).map_err(|err| {
-                                format!("failed to parse packet: {}", err)
-                            })?;
+                            num_lines += 1;
+                            let mut packet = match Packet::from_tshark(&line, self) {
+                                Ok(packet) => packet,
+                                Err(err) => {
+                                    opts.metrics.add_parse_error();
+                                    num_parse_errors += 1;
+                                    if num_parse_errors == 1 {
+                                        eprintln!("Skipping packets that fail to parse, first {:?}: {}", line, err);
+                                    }
+                                    continue;
+                                }
+                            };
+                            packet.probe = input.probe.clone();
+                            packet.index = num_lines - 1;
+                            packet_counter.fetch_add(1, Ordering::Relaxed);
+
+                            // A flow processes its queued packets before checking the clock,
+                            // and a burst ending before this packet is complete either way.
+                            if clock.is_some() && packet.time >= *clock_tx.borrow() + clock_tick {
+                                clock_tx.send_replace(packet.time);
+                            }
+                            if packet.time > last_packet.0 {
+                                last_packet = (packet.time, Instant::now());
+                            }
+
+                            if let (CaptureType::Wlan { bssids, .. }, Some(header)) = (self, &packet.header) {
+                                if !bssids.is_empty()
+                                    && !header.bssid.as_ref().is_some_and(|b| bssids.contains(b))
+                                {
+                                    continue;
+                                }
+                            }
+
+                            if packet.block_ack.is_some() {
+                                // Block Acks are sent by the receiver of the acknowledged
+                                // frames to their transmitter.
+                                let link = (
+                                    packet.dst.clone(),
+                                    packet.src.clone(),
+                                    packet.interface.clone(),
+                                );
+                                for flow_key in links.get(&link).into_iter().flatten() {
+                                    if let Some(packet_tx) = flows.get(flow_key) {
+                                        // Fails if the output has stopped, which the next
+                                        // data frame stops the capture for.
+                                        packet_tx.send(packet.clone()).await.ok();
+                                    }
+                                }
+                                continue;
+                            }
 
                             let flow_key = (
                                 packet.src.clone(),
                                 packet.dst.clone(),
                                 packet.src_port,
                                 packet.dst_port,
+                                packet.probe.clone(),
+                                packet.interface.clone(),
                             );
 
-                            match flows.entry(flow_key) {
-                                Entry::Occupied(mut entry) => {
-                                    entry.get_mut().send(packet).await?;
-                                },
+                            if let Some(header) = &packet.header {
+                                let link = (
+                                    header.transmitter.clone(),
+                                    header.receiver.clone(),
+                                    packet.interface.clone(),
+                                );
+                                links.entry(link).or_default().insert(flow_key.clone());
+                            }
+
+                            // A flow handler only stops before its sender is dropped if the
+                            // output has stopped, and then so does the capture.
+                            let sent = match flows.entry(flow_key) {
+                                Entry::Occupied(mut entry) => entry.get_mut().send(packet).await,
                                 Entry::Vacant(entry) => {
                                     let flow_key = entry.key().clone();
                                     let capture_type = self.clone();
                                     let (packet_tx, packet_rx) = mpsc::channel(100);
                                     let timeout_tx = timeout_tx.clone();
+                                    let clock = clock.clone();
+                                    num_flows += 1;
+                                    let flow_id = num_flows;
 
                                     tokio::spawn(async move {
-                                        flow_handler(flow_key, &capture_type, packet_rx, timeout_tx).await;
+                                        flow_handler(flow_key, flow_id, &capture_type, packet_rx, timeout_tx, clock).await;
                                     });
 
-                                    entry.insert(packet_tx).send(packet).await?;
+                                    entry.insert(packet_tx).send(packet).await
                                 },
+                            };
+                            if sent.is_err() {
+                                break;
                             }
                         },
                         None => break,
                     }
                 },
+                _ = sleep(Duration::from_secs_f64(clock_tick)), if clock_mode == Clock::Stream => {
+                    // Advance the capture time while no packets are read from the stream.
+                    let time = last_packet.0 + last_packet.1.elapsed().as_secs_f64();
+                    if time >= *clock_tx.borrow() + clock_tick {
+                        clock_tx.send_replace(time);
+                    }
+                },
                 Some(flow_key) = timeout_rx.recv() => {
                     // Remove flow. Drops sender and causes its flow_handler to exit.
                     flows.remove(&flow_key);
+                    links.retain(|_, flow_keys| {
+                        flow_keys.remove(&flow_key);
+                        !flow_keys.is_empty()
+                    });
                 },
             }
         }
 
-        tshark.wait().await?;
+        if num_parse_errors > 1 {
+            eprintln!("Skipped {} packets that failed to parse", num_parse_errors);
+        }
 
         Ok(())
     }
@@ -125,9 +358,11 @@ impl CaptureType {
 
 async fn flow_handler(
     flow_key: FlowKey,
+    flow_id: u32,
     capture_type: &CaptureType,
     mut rx: mpsc::Receiver<Packet>,
     timeout_tx: mpsc::Sender<FlowKey>,
+    mut clock: Option<watch::Receiver<f64>>,
 ) {
     let opts = match capture_type {
         CaptureType::Ip { opts, .. } | CaptureType::Wlan { opts, .. } => opts,
@@ -137,62 +372,211 @@ async fn flow_handler(
     let flow_timeout = Duration::from_secs_f64(FLOW_TIMEOUT);
 
     let mut flow = create_flow(capture_type);
+    let mut last_time = None;
+    opts.metrics.add_flow();
+    let mut timed_out = false;
+    // Bursts of the flow so far, and the packets of the current burst if annotating.
+    let mut num_bursts = 0;
+    let mut burst_packets = Vec::new();
+    // Program time of the last data packet or timeout, which the wait is measured from.
+    let mut idle_since = Instant::now();
 
     loop {
         let burst = flow.get_current_burst();
 
-        let timeout = if burst.is_some() {
-            sleep(burst_timeout)
-        } else {
-            sleep(flow_timeout)
+        let (duration, deadline) = match burst {
+            Some(burst) => (burst_timeout, burst.end + opts.burst_timeout),
+            None => match last_time {
+                Some(last_time) if !timed_out => (flow_timeout, last_time + FLOW_TIMEOUT),
+                _ => (flow_timeout, f64::INFINITY),
+            },
+        };
+
+        // Wait for the duration, or until the capture time passes the deadline if packet
+        // timestamps do not correlate with program time.
+        let timeout = async {
+            match clock.as_mut() {
+                None => sleep_until(idle_since + duration).await,
+                Some(clock) => loop {
+                    if *clock.borrow_and_update() > deadline {
+                        break;
+                    }
+                    if clock.changed().await.is_err() {
+                        std::future::pending::<()>().await;
+                    }
+                },
+            }
         };
 
         tokio::select! {
-            _ = timeout => {
-                if let Some(burst) = burst {
-                    opts.output_tx.send(burst.clone()).await.unwrap();
-                    flow.reset_burst();
-                    continue;
-                }
+            biased;
 
-                // Flow has timed out due to inactivity. Handler will exit
-                // when sender is dropped and None is received.
-                timeout_tx.send(flow_key.clone()).await.unwrap();
-            },
             packet = rx.recv() => {
                 match packet {
+                    // Block Acks only confirm frames of the current burst, and neither
+                    // end it nor keep the flow alive.
+                    Some(packet) if packet.block_ack.is_some() => {
+                        flow.add_packet(&packet);
+                    },
                     Some(packet) => {
+                        idle_since = Instant::now();
                         if let Some(burst) = burst {
                             // If packet timestamps do not correlate with program time,
                             // e.g. due to file read, check if burst is ready.
                             if packet.time - burst.end > opts.burst_timeout {
-                                opts.output_tx.send(burst.clone()).await.unwrap();
+                                annotate(opts, flow_id, &mut num_bursts, burst, &mut burst_packets);
+                                if !emit(opts, burst.clone()).await {
+                                    break;
+                                }
                                 flow.reset_burst();
                             }
                         }
 
-                        flow.add_packet(&packet);
+                        last_time = Some(last_time.map_or(packet.time, |t: f64| t.max(packet.time)));
+                        timed_out = false;
+
+                        if flow.add_packet(&packet) && opts.annotations.is_some() {
+                            burst_packets.push(packet.index);
+                        }
+                    },
+                    None => {
+                        // Capture has ended, output any burst still in progress.
+                        if let Some(burst) = burst {
+                            annotate(opts, flow_id, &mut num_bursts, burst, &mut burst_packets);
+                            emit(opts, burst.clone()).await;
+                        }
+                        break;
                     },
-                    None => break,
                 }
             },
+            _ = timeout => {
+                idle_since = Instant::now();
+                if let Some(burst) = burst {
+                    annotate(opts, flow_id, &mut num_bursts, burst, &mut burst_packets);
+                    if !emit(opts, burst.clone()).await {
+                        break;
+                    }
+                    flow.reset_burst();
+                    continue;
+                }
+
+                // Flow has timed out due to inactivity. Handler will exit
+                // when sender is dropped and None is received.
+                if timeout_tx.send(flow_key.clone()).await.is_err() {
+                    // Capture has ended and the flow table is gone.
+                    break;
+                }
+                timed_out = true;
+            },
         }
     }
+
+    opts.metrics.remove_flow();
+}
+
+/// Record the packets of a completed burst, numbered within its flow, if annotating.
+fn annotate(
+    opts: &CommonOptions,
+    flow_id: u32,
+    num_bursts: &mut u32,
+    burst: &Burst,
+    packets: &mut Vec<u64>,
+) {
+    if let Some(annotations) = &opts.annotations {
+        *num_bursts += 1;
+        annotations.add_burst(flow_id, *num_bursts, burst, packets);
+    }
+    packets.clear();
+}
+
+/// Send a completed burst to the output, waiting if the output is behind. Returns false
+/// if the output has stopped.
+async fn emit(opts: &CommonOptions, burst: Burst) -> bool {
+    opts.metrics.add_burst(&burst);
+
+    let burst = match opts.output_tx.try_send(burst) {
+        Ok(()) => return true,
+        Err(TrySendError::Full(burst)) => burst,
+        Err(TrySendError::Closed(_)) => return false,
+    };
+
+    let start = Instant::now();
+    if opts.output_tx.send(burst).await.is_err() {
+        return false;
+    }
+    opts.metrics.add_blocked_burst(start.elapsed());
+    true
 }
 
 #[derive(Clone, Debug)]
 struct Packet {
+    /// Index of the packet's line from tshark within its input.
+    index: u64,
     time: f64,
     src: String,
     dst: String,
+    /// For WLAN frames, the MAC payload length excluding overhead.
     data_len: u32,
+    /// WLAN protection and LLC/SNAP bytes of the MAC payload.
+    overhead: u32,
     src_port: u16,
     dst_port: u16,
     seq_number: Option<u16>,
+    tid: Option<u8>,
+    interface: Option<String>,
+    probe: Option<String>,
+    header: Option<WlanHeader>,
+    block_ack: Option<BlockAck>,
+}
+
+/// Addressing and radio metadata of a WLAN data frame.
+#[derive(Clone, Debug)]
+struct WlanHeader {
+    transmitter: String,
+    receiver: String,
+    bssid: Option<String>,
+    direction: Option<Direction>,
+    signal: Option<i32>,
+    data_rate: Option<f64>,
+    mcs: Option<u8>,
+    channel: Option<u16>,
+    retry: bool,
+}
+
+/// Sequence numbers acknowledged by a WLAN Block Ack frame.
+#[derive(Clone, Debug)]
+struct BlockAck {
+    tid: u8,
+    starting_seq_number: u16,
+    /// Bit i (least significant bit first) acknowledges starting_seq_number + i.
+    bitmap: Vec<u8>,
+}
+
+impl BlockAck {
+    /// Whether seq_number is acknowledged, or None if outside the bitmap.
+    fn acknowledges(&self, seq_number: u16) -> Option<bool> {
+        let offset = ((seq_number as i16 - self.starting_seq_number as i16) & 4095) as usize;
+        let byte = self.bitmap.get(offset / 8)?;
+        Some(byte >> (offset % 8) & 1 == 1)
+    }
 }
 
 impl Packet {
     fn from_tshark(line: &str, capture_type: &CaptureType) -> Result<Self, Box<dyn Error>> {
+        let (opts, mut packet) = match capture_type {
+            CaptureType::Ip { opts, .. } => (opts, Self::from_tshark_ip(line, capture_type)?),
+            CaptureType::Wlan { opts, gcmp, .. } => (opts, Self::from_tshark_wlan(line, *gcmp)?),
+        };
+
+        if opts.tag_interfaces {
+            let interface = line.rsplit('\t').next().filter(|i| !i.is_empty());
+            packet.interface = Some(interface.ok_or("no interface")?.to_string());
+        }
+
+        Ok(packet)
+    }
+
+    fn from_tshark_ip(line: &str, capture_type: &CaptureType) -> Result<Self, Box<dyn Error>> {
         let mut fields = line.split_whitespace();
 
         let time = fields.next().ok_or("no time")?.parse::<f64>()?;
@@ -200,34 +584,193 @@ impl Packet {
         let dst = fields.next().ok_or("no destination")?;
         let data_len = fields.next().ok_or("no length")?.parse::<u32>()?;
 
-        let (mut src_port, mut dst_port, mut seq_number) = (0, 0, None);
+        let (mut src_port, mut dst_port) = (0, 0);
 
-        match capture_type {
-            CaptureType::Ip {
-                aggregate_ports, ..
-            } if !aggregate_ports => {
-                src_port = fields.next().ok_or("no source port")?.parse::<u16>()?;
-                dst_port = fields.next().ok_or("no destination port")?.parse::<u16>()?;
-            }
-            CaptureType::Wlan { .. } => {
-                seq_number = Some(fields.next().ok_or("no sequence number")?.parse::<u16>()?);
-            }
-            _ => (),
+        if let CaptureType::Ip {
+            aggregate_ports: false,
+            ..
+        } = capture_type
+        {
+            src_port = fields.next().ok_or("no source port")?.parse::<u16>()?;
+            dst_port = fields.next().ok_or("no destination port")?.parse::<u16>()?;
         }
 
         Ok(Packet {
+            index: 0,
             time,
             src: src.to_string(),
             dst: dst.to_string(),
             data_len,
+            overhead: 0,
             src_port,
             dst_port,
-            seq_number,
+            seq_number: None,
+            tid: None,
+            interface: None,
+            probe: None,
+            header: None,
+            block_ack: None,
+        })
+    }
+
+    /// WLAN fields are tab-separated and may be empty, as Block Ack frames have no data
+    /// and data frames no Block Ack fields.
+    fn from_tshark_wlan(line: &str, gcmp: bool) -> Result<Self, Box<dyn Error>> {
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        let field = |i: usize| fields.get(i).copied().filter(|f| !f.is_empty());
+
+        let time = field(0).ok_or("no time")?.parse::<f64>()?;
+        let transmitter = field(6).ok_or("no transmitter")?;
+        let receiver = field(7).ok_or("no receiver")?;
+
+        if let Some(bitmap) = field(10) {
+            return Ok(Packet {
+                index: 0,
+                time,
+                src: transmitter.to_string(),
+                dst: receiver.to_string(),
+                data_len: 0,
+                overhead: 0,
+                src_port: 0,
+                dst_port: 0,
+                seq_number: None,
+                tid: None,
+                interface: None,
+                probe: None,
+                header: None,
+                block_ack: Some(BlockAck {
+                    tid: parse_int(field(8).ok_or("no block ack tid")?)? as u8,
+                    starting_seq_number: parse_int(field(9).ok_or("no starting sequence number")?)?
+                        as u16,
+                    bitmap: parse_hex(bitmap)?,
+                }),
+            });
+        }
+
+        let mac_payload_len = field(3).ok_or("no length")?.parse::<u32>()?;
+        let protection = match (field(18), field(19), field(20), field(21)) {
+            (_, Some(_), _, _) => Protection::Wep,
+            (_, _, Some(_), _) => Protection::Tkip,
+            (_, _, _, Some(_)) => Protection::Ccmp,
+            (Some("1" | "True"), ..) => Protection::Ccmp,
+            _ => Protection::None,
+        };
+        let overhead = (protection.overhead(gcmp) + LLC_SNAP_LEN).min(mac_payload_len);
+
+        Ok(Packet {
+            index: 0,
+            time,
+            src: field(1).ok_or("no source")?.to_string(),
+            dst: field(2).ok_or("no destination")?.to_string(),
+            data_len: mac_payload_len - overhead,
+            overhead,
+            src_port: 0,
+            dst_port: 0,
+            seq_number: Some(field(4).ok_or("no sequence number")?.parse::<u16>()?),
+            tid: field(5).map(parse_int).transpose()?.map(|tid| tid as u8),
+            interface: None,
+            probe: None,
+            header: Some(WlanHeader {
+                transmitter: transmitter.to_string(),
+                receiver: receiver.to_string(),
+                bssid: field(11).map(str::to_string),
+                direction: match field(12).map(parse_int).transpose()? {
+                    Some(1) => Some(Direction::Uplink),
+                    Some(2) => Some(Direction::Downlink),
+                    _ => None,
+                },
+                signal: field(13).map(first_value).map(str::parse).transpose()?,
+                data_rate: field(14).map(first_value).map(str::parse).transpose()?,
+                mcs: field(15).map(parse_int).transpose()?.map(|mcs| mcs as u8),
+                channel: field(16).map(parse_int).transpose()?.map(|ch| ch as u16),
+                retry: matches!(field(17), Some("1" | "True")),
+            }),
+            block_ack: None,
         })
     }
 }
 
+/// Length of the LLC/SNAP header of WLAN data frames.
+const LLC_SNAP_LEN: u32 = 8;
+
+/// Protection of a WLAN data frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Protection {
+    None,
+    Wep,
+    Tkip,
+    /// CCMP or GCMP, which have the same header format.
+    Ccmp,
+}
+
+impl Protection {
+    /// Header and trailer bytes added to the MAC payload.
+    fn overhead(&self, gcmp: bool) -> u32 {
+        match self {
+            Protection::None => 0,
+            // IV and ICV.
+            Protection::Wep => 4 + 4,
+            // IV, extended IV, MIC and ICV.
+            Protection::Tkip => 4 + 4 + 8 + 4,
+            // Header and 8-byte MIC, or 16-byte MIC for GCMP and CCMP-256.
+            Protection::Ccmp if gcmp => 8 + 16,
+            Protection::Ccmp => 8 + 8,
+        }
+    }
+}
+
+/// First value of a field with multiple occurrences, such as one per antenna.
+fn first_value(field: &str) -> &str {
+    field.split(',').next().unwrap_or_default()
+}
+
+/// Parse the first value of a field in decimal or 0x-prefixed hexadecimal.
+fn parse_int(field: &str) -> Result<u32, Box<dyn Error>> {
+    let value = first_value(field);
+    Ok(match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16)?,
+        None => value.parse()?,
+    })
+}
+
+/// Parse the first value of a bytes field, with or without colon separators.
+fn parse_hex(field: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let hex: String = first_value(field).chars().filter(|c| *c != ':').collect();
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            Ok(u8::from_str_radix(
+                hex.get(i..i + 2).ok_or("odd length")?,
+                16,
+            )?)
+        })
+        .collect()
+}
+
 impl Burst {
+    /// Seconds between the first and last packet.
+    pub fn duration(&self) -> f64 {
+        self.end - self.start
+    }
+
+    /// Key of the flow the burst belongs to.
+    pub fn flow_key(&self) -> FlowKey {
+        (
+            self.src.clone(),
+            self.dst.clone(),
+            self.src_port,
+            self.dst_port,
+            self.probe.clone(),
+            self.interface.clone(),
+        )
+    }
+
+    /// Average throughput in bits/s, or None if all packets have the same timestamp.
+    pub fn rate(&self) -> Option<f64> {
+        let duration = self.duration();
+        (duration > 0.0).then(|| self.size as f64 * 8.0 / duration)
+    }
+
     fn from_packet(p: &Packet) -> Self {
         Burst {
             src: p.src.clone(),
@@ -238,12 +781,56 @@ impl Burst {
             end: p.time,
             num_packets: 1,
             size: p.data_len,
+            interface: p.interface.clone(),
+            probe: p.probe.clone(),
+            wlan: p.header.as_ref().map(|header| WlanBurst {
+                bssid: header.bssid.clone(),
+                transmitter: header.transmitter.clone(),
+                receiver: header.receiver.clone(),
+                direction: header.direction,
+                radio: RadioStats::default(),
+                num_estimated: 0,
+                estimated_size: 0,
+                raw_size: p.data_len + p.overhead,
+            }),
+        }
+    }
+
+    fn add_packet(&mut self, p: &Packet) {
+        self.end = p.time;
+        self.num_packets += 1;
+        self.size += p.data_len;
+        if let Some(wlan) = self.wlan.as_mut() {
+            wlan.raw_size += p.data_len + p.overhead;
+        }
+    }
+
+    /// Add frames missed by the capture with an estimated length and overhead each.
+    fn add_estimated(&mut self, num_frames: u16, estimated_len: u32, overhead: u32) {
+        self.num_packets += num_frames;
+        self.size += estimated_len * num_frames as u32;
+        if let Some(wlan) = self.wlan.as_mut() {
+            wlan.num_estimated += num_frames;
+            wlan.estimated_size += estimated_len * num_frames as u32;
+            wlan.raw_size += (estimated_len + overhead) * num_frames as u32;
+        }
+    }
+
+    fn remove_estimated(&mut self, num_frames: u16, estimated_len: u32, overhead: u32) {
+        self.num_packets -= num_frames;
+        self.size -= estimated_len * num_frames as u32;
+        if let Some(wlan) = self.wlan.as_mut() {
+            wlan.num_estimated -= num_frames;
+            wlan.estimated_size -= estimated_len * num_frames as u32;
+            wlan.raw_size -= (estimated_len + overhead) * num_frames as u32;
         }
     }
 }
 
 trait Flow: Send {
-    fn add_packet(&mut self, p: &Packet);
+    /// Add a packet to the current burst, or start a burst with it. Returns whether the
+    /// packet was counted in the burst, rather than e.g. ignored as a retransmission.
+    fn add_packet(&mut self, p: &Packet) -> bool;
     fn get_current_burst(&self) -> &Option<Burst>;
     fn reset_burst(&mut self);
 }
@@ -253,11 +840,18 @@ fn create_flow(capture_type: &CaptureType) -> Box<dyn Flow> {
         CaptureType::Ip { .. } => Box::new(IpFlow {
             current_burst: None,
         }),
-        CaptureType::Wlan { .. } => Box::new(WlanFlow {
-            no_estimation: false,
-            max_deviation: 0,
-            expected_seq_number: 0,
-            last_packet_len: 0,
+        CaptureType::Wlan {
+            no_estimation,
+            estimator,
+            max_deviation,
+            ..
+        } => Box::new(WlanFlow {
+            no_estimation: *no_estimation,
+            estimator: *estimator,
+            max_deviation: *max_deviation,
+            history: SizeHistory::default(),
+            sequences: HashMap::new(),
+            radio: RadioAccumulator::default(),
             current_burst: None,
         }),
     }
@@ -268,17 +862,16 @@ struct IpFlow {
 }
 
 impl Flow for IpFlow {
-    fn add_packet(&mut self, p: &Packet) {
+    fn add_packet(&mut self, p: &Packet) -> bool {
         if self.current_burst.is_none() {
             self.current_burst = Some(Burst::from_packet(p));
-            return;
+            return true;
         }
 
         let burst = self.current_burst.as_mut().unwrap();
 
-        burst.end = p.time;
-        burst.num_packets += 1;
-        burst.size += p.data_len;
+        burst.add_packet(p);
+        true
     }
 
     fn get_current_burst(&self) -> &Option<Burst> {
@@ -290,66 +883,295 @@ impl Flow for IpFlow {
     }
 }
 
+/// Sequence number state of one TID. QoS data frames of each TID have their own
+/// sequence number space.
+struct Sequence {
+    expected_seq_number: u16,
+    last_packet_len: u32,
+    /// Frames missed by the capture, by sequence number, that have been estimated.
+    missed: HashMap<u16, MissedFrame>,
+}
+
+impl Sequence {
+    fn new(p: &Packet) -> Self {
+        Sequence {
+            expected_seq_number: (p.seq_number.unwrap() + 1) & 4095,
+            last_packet_len: p.data_len,
+            missed: HashMap::new(),
+        }
+    }
+}
+
+struct MissedFrame {
+    estimated_len: u32,
+    overhead: u32,
+    /// Whether the frame is included in the burst. Frames that a Block Ack reports as
+    /// not received are excluded until acknowledged or retransmitted.
+    counted: bool,
+}
+
+/// Sums of the radio metadata of the frames in a burst.
+#[derive(Default)]
+struct RadioAccumulator {
+    num_frames: u32,
+    num_retries: u32,
+    num_signals: u32,
+    signal_sum: f64,
+    min_signal: Option<i32>,
+    num_data_rates: u32,
+    data_rate_sum: f64,
+    mcs_counts: HashMap<u8, u32>,
+    channel: Option<u16>,
+}
+
+impl RadioAccumulator {
+    fn add(&mut self, header: &WlanHeader) {
+        self.num_frames += 1;
+        self.num_retries += header.retry as u32;
+
+        if let Some(signal) = header.signal {
+            self.num_signals += 1;
+            self.signal_sum += signal as f64;
+            self.min_signal = Some(self.min_signal.map_or(signal, |min| min.min(signal)));
+        }
+
+        if let Some(data_rate) = header.data_rate {
+            self.num_data_rates += 1;
+            self.data_rate_sum += data_rate;
+        }
+
+        if let Some(mcs) = header.mcs {
+            *self.mcs_counts.entry(mcs).or_default() += 1;
+        }
+
+        self.channel = header.channel.or(self.channel);
+    }
+
+    fn stats(&self) -> RadioStats {
+        RadioStats {
+            mean_signal: (self.num_signals > 0).then(|| self.signal_sum / self.num_signals as f64),
+            min_signal: self.min_signal,
+            dominant_mcs: self
+                .mcs_counts
+                .iter()
+                .max_by_key(|(mcs, count)| (**count, std::cmp::Reverse(**mcs)))
+                .map(|(mcs, _)| *mcs),
+            mean_data_rate: (self.num_data_rates > 0)
+                .then(|| self.data_rate_sum / self.num_data_rates as f64),
+            channel: self.channel,
+            retry_fraction: self.num_retries as f64 / self.num_frames.max(1) as f64,
+        }
+    }
+}
+
+/// Number of recent frame sizes of a flow used for estimation.
+const SIZE_HISTORY: usize = 256;
+
+/// Recent frame sizes of a flow, kept across bursts.
+#[derive(Default)]
+struct SizeHistory {
+    sizes: VecDeque<u32>,
+}
+
+impl SizeHistory {
+    fn add(&mut self, size: u32) {
+        if self.sizes.len() == SIZE_HISTORY {
+            self.sizes.pop_front();
+        }
+        self.sizes.push_back(size);
+    }
+
+    /// Estimated size of each frame missed between frames of size last and current.
+    fn estimate(&self, estimator: Estimator, last: u32, current: u32) -> u32 {
+        let mean = (last + current) / 2;
+
+        match estimator {
+            Estimator::Last => last,
+            Estimator::Mean => mean,
+            Estimator::Median => {
+                // Frames within 10% of the largest recent frame are considered full-size.
+                let max = self.sizes.iter().copied().max().unwrap_or(mean);
+                let mut full_size: Vec<u32> = self
+                    .sizes
+                    .iter()
+                    .copied()
+                    .filter(|&size| size >= max - max / 10)
+                    .collect();
+                full_size.sort_unstable();
+                full_size.get(full_size.len() / 2).copied().unwrap_or(mean)
+            }
+            Estimator::Mode => {
+                let mut counts = HashMap::<u32, u32>::new();
+                for &size in &self.sizes {
+                    *counts.entry(size).or_default() += 1;
+                }
+                counts
+                    .into_iter()
+                    .max_by_key(|&(size, count)| (count, size))
+                    .map_or(mean, |(size, _)| size)
+            }
+        }
+    }
+}
+
 struct WlanFlow {
     no_estimation: bool,
+    estimator: Estimator,
     max_deviation: u16,
-    expected_seq_number: u16,
-    last_packet_len: u32,
+    history: SizeHistory,
+    sequences: HashMap<u8, Sequence>,
+    radio: RadioAccumulator,
     current_burst: Option<Burst>,
 }
 
-impl Flow for WlanFlow {
-    fn add_packet(&mut self, p: &Packet) {
+impl WlanFlow {
+    fn add_block_ack(&mut self, block_ack: &BlockAck) {
+        let (Some(burst), Some(sequence)) = (
+            self.current_burst.as_mut(),
+            self.sequences.get_mut(&block_ack.tid),
+        ) else {
+            return;
+        };
+
+        sequence.missed.retain(|&seq_number, frame| {
+            match block_ack.acknowledges(seq_number) {
+                Some(true) => {
+                    // Delivered, the estimate is final.
+                    if !frame.counted {
+                        burst.add_estimated(1, frame.estimated_len, frame.overhead);
+                    }
+                    false
+                }
+                Some(false) => {
+                    // Lost, the frame will be retransmitted with the same sequence number.
+                    if frame.counted {
+                        frame.counted = false;
+                        burst.remove_estimated(1, frame.estimated_len, frame.overhead);
+                    }
+                    true
+                }
+                None => true,
+            }
+        });
+    }
+
+    /// Returns whether the frame was counted in the burst.
+    fn add_data_frame(&mut self, p: &Packet) -> bool {
+        let seq_number = p.seq_number.unwrap();
+        let tid = p.tid.unwrap_or(0);
+
         if self.current_burst.is_none() {
             self.current_burst = Some(Burst::from_packet(p));
-            self.expected_seq_number = (p.seq_number.unwrap() + 1) & 4095;
-            self.last_packet_len = p.data_len;
-            return;
+            self.sequences.clear();
+            self.sequences.insert(tid, Sequence::new(p));
+            return true;
         }
 
         let burst = self.current_burst.as_mut().unwrap();
-        let seq_number = p.seq_number.unwrap();
 
-        if seq_number == self.expected_seq_number {
-            self.expected_seq_number = (seq_number + 1) & 4095;
-            self.last_packet_len = p.data_len;
-            burst.end = p.time;
-            burst.num_packets += 1;
-            burst.size += p.data_len;
-            return;
+        let sequence = match self.sequences.entry(tid) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                // First frame of the TID in this burst.
+                entry.insert(Sequence::new(p));
+                burst.add_packet(p);
+                return true;
+            }
+        };
+
+        if seq_number == sequence.expected_seq_number {
+            sequence.expected_seq_number = (seq_number + 1) & 4095;
+            sequence.last_packet_len = p.data_len;
+            burst.add_packet(p);
+            return true;
         }
 
         // Sequence number not what we expect.
-        let diff = (seq_number as i16 - self.expected_seq_number as i16) & 4095;
+        let diff = (seq_number as i16 - sequence.expected_seq_number as i16) & 4095;
         let signed_diff = if diff <= 2048 { diff } else { diff - 4096 };
 
         // Check if frame already added. Could be a retransmission.
         // Not enough to filter on the retransmission bit as the first frame might be lost.
         if -(self.max_deviation as i16) < signed_diff && signed_diff < 0 {
+            // A frame missed earlier, e.g. reordered within an A-MPDU. Replace its estimate.
+            let counted = match sequence.missed.remove(&seq_number) {
+                Some(frame) => {
+                    if frame.counted {
+                        burst.remove_estimated(1, frame.estimated_len, frame.overhead);
+                    }
+                    burst.add_packet(p);
+                    true
+                }
+                None => false,
+            };
             burst.end = p.time;
-            return;
+            return counted;
         }
 
         // Sequence number is further along than what we expect. Could be lost frame(s).
         if 0 < signed_diff && signed_diff < self.max_deviation as i16 {
-            if !self.no_estimation {
-                let estimate = (self.last_packet_len + p.data_len) / 2;
-                burst.num_packets += diff as u16;
-                burst.size += estimate * diff as u32;
-            } else {
+            if self.no_estimation {
                 // Accept only this frame if estimation is disabled.
-                burst.num_packets += 1;
-                burst.size += p.data_len;
+                burst.add_packet(p);
+            } else {
+                // The frames missed before this one are counted with the estimated size,
+                // and this frame is not counted by itself.
+                let estimate =
+                    self.history
+                        .estimate(self.estimator, sequence.last_packet_len, p.data_len);
+                for i in 0..diff as u16 {
+                    let missed_seq_number = (sequence.expected_seq_number + i) & 4095;
+                    sequence.missed.insert(
+                        missed_seq_number,
+                        MissedFrame {
+                            estimated_len: estimate,
+                            overhead: p.overhead,
+                            counted: true,
+                        },
+                    );
+                }
+                burst.add_estimated(diff as u16, estimate, p.overhead);
+                burst.end = p.time;
             }
             // Bring the expected sequence number in line with the new frame.
-            self.expected_seq_number = (seq_number + 1) & 4095;
-            self.last_packet_len = p.data_len;
-            burst.end = p.time;
+            sequence.expected_seq_number = (seq_number + 1) & 4095;
+            sequence.last_packet_len = p.data_len;
+
+            // Forget missed frames too far behind to be replaced.
+            let expected = sequence.expected_seq_number as i16;
+            let max_deviation = self.max_deviation as i16;
+            sequence
+                .missed
+                .retain(|&seq, _| (expected - seq as i16) & 4095 < max_deviation);
+            true
         } else {
             // Larger deviation than allowed, go to next expected.
-            self.expected_seq_number = (self.expected_seq_number + 1) & 4095;
+            sequence.expected_seq_number = (sequence.expected_seq_number + 1) & 4095;
+            false
         }
     }
+}
+
+impl Flow for WlanFlow {
+    fn add_packet(&mut self, p: &Packet) -> bool {
+        if let Some(block_ack) = &p.block_ack {
+            self.add_block_ack(block_ack);
+            return false;
+        }
+
+        if self.current_burst.is_none() {
+            self.radio = RadioAccumulator::default();
+        }
+        self.radio.add(p.header.as_ref().unwrap());
+        self.history.add(p.data_len);
+
+        let counted = self.add_data_frame(p);
+
+        if let Some(wlan) = self.current_burst.as_mut().and_then(|b| b.wlan.as_mut()) {
+            wlan.radio = self.radio.stats();
+        }
+        counted
+    }
 
     fn get_current_burst(&self) -> &Option<Burst> {
         &self.current_burst
@@ -359,3 +1181,92 @@ impl Flow for WlanFlow {
         self.current_burst = None;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wlan_flow(no_estimation: bool) -> WlanFlow {
+        WlanFlow {
+            no_estimation,
+            estimator: Estimator::Mean,
+            max_deviation: 50,
+            history: SizeHistory::default(),
+            sequences: HashMap::new(),
+            radio: RadioAccumulator::default(),
+            current_burst: None,
+        }
+    }
+
+    fn frame(time: f64, seq_number: u16, data_len: u32) -> Packet {
+        Packet {
+            index: 0,
+            time,
+            src: "10.0.0.1".to_string(),
+            dst: "10.0.0.2".to_string(),
+            data_len,
+            overhead: 0,
+            src_port: 443,
+            dst_port: 5000,
+            seq_number: Some(seq_number),
+            tid: Some(0),
+            interface: None,
+            probe: None,
+            header: Some(WlanHeader {
+                transmitter: "00:00:00:00:00:01".to_string(),
+                receiver: "00:00:00:00:00:02".to_string(),
+                bssid: None,
+                direction: None,
+                signal: None,
+                data_rate: None,
+                mcs: None,
+                channel: None,
+                retry: false,
+            }),
+            block_ack: None,
+        }
+    }
+
+    fn summary(flow: &WlanFlow) -> (u16, u32, u16, f64) {
+        let burst = flow.get_current_burst().as_ref().unwrap();
+        let num_estimated = burst.wlan.as_ref().unwrap().num_estimated;
+        (burst.num_packets, burst.size, num_estimated, burst.end)
+    }
+
+    #[test]
+    fn sequence_gap_is_counted_as_estimated_frames() {
+        let mut flow = wlan_flow(false);
+        flow.add_packet(&frame(1.0, 10, 1000));
+        flow.add_packet(&frame(1.1, 11, 1000));
+        // 12 and 13 are missed, and counted as two frames of the mean of 1000 and 500.
+        assert!(flow.add_packet(&frame(1.2, 14, 500)));
+        assert_eq!(summary(&flow), (4, 3500, 2, 1.2));
+
+        flow.add_packet(&frame(1.3, 15, 1000));
+        assert_eq!(summary(&flow), (5, 4500, 2, 1.3));
+    }
+
+    #[test]
+    fn late_frame_replaces_its_estimate() {
+        let mut flow = wlan_flow(false);
+        flow.add_packet(&frame(1.0, 10, 1000));
+        // 11 and 12 are missed.
+        flow.add_packet(&frame(1.1, 13, 500));
+        assert_eq!(summary(&flow), (3, 2500, 2, 1.1));
+
+        assert!(flow.add_packet(&frame(1.2, 11, 900)));
+        assert_eq!(summary(&flow), (3, 2650, 1, 1.2));
+        // Already counted, e.g. a retransmission.
+        assert!(!flow.add_packet(&frame(1.3, 11, 900)));
+        assert_eq!(summary(&flow), (3, 2650, 1, 1.3));
+    }
+
+    #[test]
+    fn sequence_gap_without_estimation_counts_the_frame() {
+        let mut flow = wlan_flow(true);
+        flow.add_packet(&frame(1.0, 10, 1000));
+        flow.add_packet(&frame(1.1, 11, 1000));
+        assert!(flow.add_packet(&frame(1.2, 14, 500)));
+        assert_eq!(summary(&flow), (3, 2500, 0, 1.2));
+    }
+}
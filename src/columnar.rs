@@ -0,0 +1,261 @@
+use std::error::Error;
+use std::fs::File;
+use std::sync::Arc;
+
+use arrow::array::{
+    ArrayRef, Float64Array, Int32Array, RecordBatch, StringArray, TimestampMicrosecondArray,
+    UInt16Array, UInt32Array, UInt8Array,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::ipc::writer::FileWriter;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use tokio::sync::mpsc;
+use tokio::task::{spawn_blocking, JoinHandle};
+
+use crate::capture::{Burst, WlanBurst};
+use crate::output::{BurstFilter, Format};
+
+enum Writer {
+    Parquet(ArrowWriter<File>),
+    Arrow(FileWriter<File>),
+}
+
+impl Writer {
+    fn write(&mut self, batch: &RecordBatch) -> Result<(), Box<dyn Error>> {
+        match self {
+            // Each batch is written as a row group of its own.
+            Writer::Parquet(writer) => {
+                writer.write(batch)?;
+                writer.flush()?;
+            }
+            Writer::Arrow(writer) => writer.write(batch)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Writer::Parquet(writer) => {
+                writer.close()?;
+            }
+            Writer::Arrow(mut writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+
+    /// Write a batch on a thread where blocking is allowed, as writing blocks on disk I/O.
+    async fn write_blocking(mut self, batch: RecordBatch) -> Result<Self, String> {
+        spawn_blocking(move || {
+            self.write(&batch)
+                .map(|()| self)
+                .map_err(|err| err.to_string())
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn finish_blocking(self) -> Result<(), String> {
+        spawn_blocking(move || self.finish().map_err(|err| err.to_string()))
+            .await
+            .unwrap()
+    }
+}
+
+/// Writes bursts to a Parquet or Arrow IPC file, with a column per burst field.
+///
+/// WLAN fields are prefixed with wlan_ and null for bursts of IP packets. Bursts are
+/// written in row groups, or record batches for Arrow, of a fixed number of rows. The file
+/// is only complete once the capture has ended.
+pub struct ColumnarWriter {
+    filter: BurstFilter,
+    path: String,
+    writer: Option<Writer>,
+    row_group_size: usize,
+}
+
+impl ColumnarWriter {
+    /// Create the file at path, truncating it if it exists. Format must be Parquet or Arrow.
+    pub fn create(
+        filter: BurstFilter,
+        format: Format,
+        path: &str,
+        row_group_size: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let file = File::create(path)
+            .map_err(|err| format!("failed to create output file {}: {}", path, err))?;
+
+        let schema = schema();
+        let writer = match format {
+            Format::Parquet => {
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                    .set_max_row_group_size(row_group_size.max(1))
+                    .build();
+                Writer::Parquet(ArrowWriter::try_new(file, schema, Some(properties))?)
+            }
+            Format::Arrow => Writer::Arrow(FileWriter::try_new(file, &schema)?),
+            Format::Text | Format::Jsonl => unreachable!("not a columnar format"),
+        };
+
+        Ok(ColumnarWriter {
+            filter,
+            path: path.to_string(),
+            writer: Some(writer),
+            row_group_size: row_group_size.max(1),
+        })
+    }
+
+    pub async fn start(&mut self) -> (mpsc::Sender<Burst>, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::channel::<Burst>(100);
+
+        let filter = self.filter.clone();
+        let path = self.path.clone();
+        let mut writer = self.writer.take().expect("writer already started");
+        let row_group_size = self.row_group_size;
+
+        let handle = tokio::spawn(async move {
+            let schema = schema();
+            let mut rows = Vec::with_capacity(row_group_size);
+
+            let result = async {
+                while let Some(burst) = rx.recv().await {
+                    if !filter.matches(&burst) {
+                        continue;
+                    }
+
+                    rows.push(burst);
+                    if rows.len() == row_group_size {
+                        let batch = record_batch(&schema, &rows);
+                        rows.clear();
+                        writer = writer.write_blocking(batch).await?;
+                    }
+                }
+
+                if !rows.is_empty() {
+                    writer = writer.write_blocking(record_batch(&schema, &rows)).await?;
+                }
+                writer.finish_blocking().await
+            };
+
+            if let Err(err) = result.await {
+                eprintln!("Failed to write bursts to {}: {}", path, err);
+            }
+        });
+
+        (tx, handle)
+    }
+}
+
+fn schema() -> SchemaRef {
+    let time = DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()));
+
+    Arc::new(Schema::new(vec![
+        Field::new("src", DataType::Utf8, false),
+        Field::new("dst", DataType::Utf8, false),
+        Field::new("src_port", DataType::UInt16, false),
+        Field::new("dst_port", DataType::UInt16, false),
+        Field::new("start", time.clone(), false),
+        Field::new("end", time, false),
+        Field::new("num_packets", DataType::UInt16, false),
+        Field::new("size", DataType::UInt32, false),
+        Field::new("duration", DataType::Float64, false),
+        Field::new("rate", DataType::Float64, true),
+        Field::new("interface", DataType::Utf8, true),
+        Field::new("probe", DataType::Utf8, true),
+        Field::new("wlan_bssid", DataType::Utf8, true),
+        Field::new("wlan_transmitter", DataType::Utf8, true),
+        Field::new("wlan_receiver", DataType::Utf8, true),
+        Field::new("wlan_direction", DataType::Utf8, true),
+        Field::new("wlan_mean_signal", DataType::Float64, true),
+        Field::new("wlan_min_signal", DataType::Int32, true),
+        Field::new("wlan_dominant_mcs", DataType::UInt8, true),
+        Field::new("wlan_mean_data_rate", DataType::Float64, true),
+        Field::new("wlan_channel", DataType::UInt16, true),
+        Field::new("wlan_retry_fraction", DataType::Float64, true),
+        Field::new("wlan_num_estimated", DataType::UInt16, true),
+        Field::new("wlan_estimated_size", DataType::UInt32, true),
+        Field::new("wlan_raw_size", DataType::UInt32, true),
+    ]))
+}
+
+fn record_batch(schema: &SchemaRef, bursts: &[Burst]) -> RecordBatch {
+    let wlan: Vec<Option<&WlanBurst>> = bursts.iter().map(|b| b.wlan.as_ref()).collect();
+    let micros = |seconds: f64| (seconds * 1e6).round() as i64;
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(bursts.iter().map(|b| &b.src))),
+        Arc::new(StringArray::from_iter_values(bursts.iter().map(|b| &b.dst))),
+        Arc::new(UInt16Array::from_iter_values(
+            bursts.iter().map(|b| b.src_port),
+        )),
+        Arc::new(UInt16Array::from_iter_values(
+            bursts.iter().map(|b| b.dst_port),
+        )),
+        Arc::new(
+            TimestampMicrosecondArray::from_iter_values(bursts.iter().map(|b| micros(b.start)))
+                .with_timezone("UTC"),
+        ),
+        Arc::new(
+            TimestampMicrosecondArray::from_iter_values(bursts.iter().map(|b| micros(b.end)))
+                .with_timezone("UTC"),
+        ),
+        Arc::new(UInt16Array::from_iter_values(
+            bursts.iter().map(|b| b.num_packets),
+        )),
+        Arc::new(UInt32Array::from_iter_values(bursts.iter().map(|b| b.size))),
+        Arc::new(Float64Array::from_iter_values(
+            bursts.iter().map(|b| b.duration()),
+        )),
+        Arc::new(Float64Array::from_iter(bursts.iter().map(|b| b.rate()))),
+        Arc::new(StringArray::from_iter(
+            bursts.iter().map(|b| b.interface.as_deref()),
+        )),
+        Arc::new(StringArray::from_iter(
+            bursts.iter().map(|b| b.probe.as_deref()),
+        )),
+        Arc::new(StringArray::from_iter(
+            wlan.iter().map(|w| w.and_then(|w| w.bssid.as_deref())),
+        )),
+        Arc::new(StringArray::from_iter(
+            wlan.iter().map(|w| w.map(|w| w.transmitter.as_str())),
+        )),
+        Arc::new(StringArray::from_iter(
+            wlan.iter().map(|w| w.map(|w| w.receiver.as_str())),
+        )),
+        Arc::new(StringArray::from_iter(
+            wlan.iter()
+                .map(|w| w.and_then(|w| w.direction).map(|d| d.to_string())),
+        )),
+        Arc::new(Float64Array::from_iter(
+            wlan.iter().map(|w| w.and_then(|w| w.radio.mean_signal)),
+        )),
+        Arc::new(Int32Array::from_iter(
+            wlan.iter().map(|w| w.and_then(|w| w.radio.min_signal)),
+        )),
+        Arc::new(UInt8Array::from_iter(
+            wlan.iter().map(|w| w.and_then(|w| w.radio.dominant_mcs)),
+        )),
+        Arc::new(Float64Array::from_iter(
+            wlan.iter().map(|w| w.and_then(|w| w.radio.mean_data_rate)),
+        )),
+        Arc::new(UInt16Array::from_iter(
+            wlan.iter().map(|w| w.and_then(|w| w.radio.channel)),
+        )),
+        Arc::new(Float64Array::from_iter(
+            wlan.iter().map(|w| w.map(|w| w.radio.retry_fraction)),
+        )),
+        Arc::new(UInt16Array::from_iter(
+            wlan.iter().map(|w| w.map(|w| w.num_estimated)),
+        )),
+        Arc::new(UInt32Array::from_iter(
+            wlan.iter().map(|w| w.map(|w| w.estimated_size)),
+        )),
+        Arc::new(UInt32Array::from_iter(
+            wlan.iter().map(|w| w.map(|w| w.raw_size)),
+        )),
+    ];
+
+    RecordBatch::try_new(schema.clone(), columns).unwrap()
+}
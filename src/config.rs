@@ -0,0 +1,196 @@
+use std::error::Error;
+use std::ffi::OsString;
+
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use toml::{Table, Value};
+
+/// Options that select the configuration rather than being part of it.
+const CONFIG_OPTIONS: &[&str] = &["config", "profile", "print-config", "help", "version"];
+
+/// Read a configuration file, with the values of a profile in [profile.<name>] replacing
+/// those at the top level.
+pub fn load(path: &str, profile: Option<&str>) -> Result<Table, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| format!("failed to read config {}: {}", path, err))?;
+    let table: Table = text
+        .parse()
+        .map_err(|err| format!("failed to parse config {}: {}", path, err))?;
+    let mut table = normalize(table);
+
+    let profiles = match table.remove("profile") {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => return Err(format!("profile in {} must be a table", path).into()),
+        None => Table::new(),
+    };
+
+    if let Some(name) = profile {
+        let Some(values) = profiles.get(name) else {
+            let names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+            return Err(format!(
+                "no profile {} in {}, profiles: {}",
+                name,
+                path,
+                names.join(", ")
+            )
+            .into());
+        };
+        let Value::Table(values) = values else {
+            return Err(format!("profile {} in {} must be a table", name, path).into());
+        };
+        table.extend(normalize(values.clone()));
+    }
+
+    Ok(table)
+}
+
+/// The table with - between the words of keys, so that keys written either way replace each other.
+fn normalize(table: Table) -> Table {
+    table
+        .into_iter()
+        .map(|(key, value)| (key.replace('_', "-"), value))
+        .collect()
+}
+
+/// Command line arguments for the values of a configuration, for the options of command
+/// that were not given on the command line, or conflict with one that was.
+///
+/// Keys are long option names, with - or _ between words. Commands, their options and the
+/// filter argument are not part of a configuration.
+pub fn to_args(
+    config: &Table,
+    command: &Command,
+    matches: &ArgMatches,
+) -> Result<Vec<OsString>, Box<dyn Error>> {
+    let given: Vec<&Arg> = command
+        .get_arguments()
+        .filter(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine))
+        .collect();
+
+    let mut args = Vec::new();
+    for (key, value) in config {
+        let Some(arg) = find_arg(command, key) else {
+            // Commands are given with their options, and the filter argument after them,
+            // on the command line only.
+            if let Some(sub) = command
+                .get_subcommands()
+                .find(|sub| find_arg(sub, key).is_some())
+            {
+                return Err(format!(
+                    "{} is an option of the {} command, which can only be given on the command line",
+                    key,
+                    sub.get_name()
+                )
+                .into());
+            }
+            return Err(format!("unknown option {}", key).into());
+        };
+        let long = arg.get_long().unwrap();
+
+        let overridden = given.iter().any(|given| {
+            given.get_id() == arg.get_id()
+                || command
+                    .get_arg_conflicts_with(arg)
+                    .iter()
+                    .any(|conflict| conflict.get_id() == given.get_id())
+        });
+        if overridden {
+            continue;
+        }
+
+        match (arg.get_action(), value) {
+            (ArgAction::SetTrue, Value::Boolean(true)) => args.push(format!("--{}", long).into()),
+            (ArgAction::SetTrue, Value::Boolean(false)) => {}
+            (ArgAction::SetTrue, _) => return Err(format!("{} must be true or false", key).into()),
+            (ArgAction::Append, Value::Array(values)) => {
+                for value in values {
+                    args.push(format!("--{}={}", long, scalar(key, value)?).into());
+                }
+            }
+            (_, value) => args.push(format!("--{}={}", long, scalar(key, value)?).into()),
+        }
+    }
+
+    Ok(args)
+}
+
+/// The effective configuration of parsed arguments as TOML.
+///
+/// Options left at their default value are commented out, as some of them are only
+/// accepted together with other options.
+pub fn render(command: &Command, matches: &ArgMatches) -> String {
+    let mut table = Table::new();
+    let mut defaults = Table::new();
+
+    for arg in command.get_arguments() {
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        if CONFIG_OPTIONS.contains(&long) {
+            continue;
+        }
+        let id = arg.get_id().as_str();
+
+        let value = match arg.get_action() {
+            ArgAction::SetTrue => Value::Boolean(matches.get_flag(id)),
+            ArgAction::Append => {
+                let values: Vec<Value> = matches
+                    .get_raw(id)
+                    .into_iter()
+                    .flatten()
+                    .map(|value| typed(&value.to_string_lossy()))
+                    .collect();
+                if values.is_empty() {
+                    continue;
+                }
+                Value::Array(values)
+            }
+            _ => match matches.get_raw(id).and_then(|mut values| values.next()) {
+                Some(value) => typed(&value.to_string_lossy()),
+                None => continue,
+            },
+        };
+
+        match matches.value_source(id) {
+            Some(ValueSource::DefaultValue) | None => defaults.insert(long.to_string(), value),
+            _ => table.insert(long.to_string(), value),
+        };
+    }
+
+    let mut text = toml::to_string(&table).unwrap();
+    for line in toml::to_string(&defaults).unwrap().lines() {
+        text.push_str(&format!("# {}\n", line));
+    }
+    text
+}
+
+fn find_arg<'a>(command: &'a Command, key: &str) -> Option<&'a Arg> {
+    let key = key.replace('_', "-");
+    command.get_arguments().find(|arg| {
+        arg.get_long()
+            .is_some_and(|long| long.replace('_', "-") == key && !CONFIG_OPTIONS.contains(&long))
+    })
+}
+
+/// A single value as an argument.
+fn scalar(key: &str, value: &Value) -> Result<String, Box<dyn Error>> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Integer(value) => Ok(value.to_string()),
+        Value::Float(value) => Ok(value.to_string()),
+        Value::Boolean(value) => Ok(value.to_string()),
+        _ => Err(format!("{} must be a string, number or boolean", key).into()),
+    }
+}
+
+/// A value as an integer or float if it is one, so that the configuration can be edited
+/// as written by hand.
+fn typed(value: &str) -> Value {
+    if let Ok(integer) = value.parse::<i64>() {
+        return Value::Integer(integer);
+    }
+    match value.parse::<f64>() {
+        Ok(float) if float.is_finite() => Value::Float(float),
+        _ => Value::String(value.to_string()),
+    }
+}
@@ -0,0 +1,302 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{stdout, Write};
+use std::path::Path;
+
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::capture::Burst;
+use crate::output::BurstFilter;
+
+/// A media segment from a ground-truth log.
+///
+/// Flow fields are optional. Those that are set must equal the corresponding field of a
+/// burst for the two to be matched. As segments are downloaded, the source is normally
+/// the server and the destination the client.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Segment {
+    /// Unix timestamp of the segment request.
+    pub time: f64,
+    /// Size of the segment in bytes.
+    pub size: u32,
+    pub src: Option<String>,
+    pub dst: Option<String>,
+    pub src_port: Option<u16>,
+    pub dst_port: Option<u16>,
+}
+
+impl Segment {
+    fn matches_flow(&self, burst: &Burst) -> bool {
+        self.src.as_ref().is_none_or(|src| *src == burst.src)
+            && self.dst.as_ref().is_none_or(|dst| *dst == burst.dst)
+            && self.src_port.is_none_or(|port| port == burst.src_port)
+            && self.dst_port.is_none_or(|port| port == burst.dst_port)
+    }
+}
+
+/// Read segments from a CSV file with a header row, or a JSON array of objects if the
+/// file has a `.json` extension. Segments must have a size greater than 0.
+pub fn read_segments(path: &str) -> Result<Vec<Segment>, Box<dyn Error>> {
+    let is_json = Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+
+    let segments = if is_json {
+        serde_json::from_reader(File::open(path)?)?
+    } else {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?
+            .deserialize()
+            .collect::<Result<Vec<Segment>, _>>()?
+    };
+
+    // Size errors are relative to the segment size.
+    if let Some((i, segment)) = segments.iter().enumerate().find(|(_, s)| s.size == 0) {
+        return Err(format!("segment {} at time {} has a size of 0", i + 1, segment.time).into());
+    }
+
+    Ok(segments)
+}
+
+/// Collects bursts and matches them against ground-truth segments once the capture ends.
+pub struct Evaluator {
+    segments: Vec<Segment>,
+    time_tolerance: f64,
+    filter: BurstFilter,
+}
+
+impl Evaluator {
+    pub fn new(segments: Vec<Segment>, time_tolerance: f64, filter: BurstFilter) -> Self {
+        Evaluator {
+            segments,
+            time_tolerance,
+            filter,
+        }
+    }
+
+    /// The returned handle completes with the report written to stdout when all senders
+    /// have been dropped.
+    pub async fn start(&mut self) -> (mpsc::Sender<Burst>, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::channel::<Burst>(100);
+
+        let segments = self.segments.clone();
+        let time_tolerance = self.time_tolerance;
+        let filter = self.filter.clone();
+
+        let handle = tokio::spawn(async move {
+            let mut bursts = Vec::new();
+
+            while let Some(burst) = rx.recv().await {
+                if filter.matches(&burst) {
+                    bursts.push(burst);
+                }
+            }
+
+            let evaluation = Evaluation::new(segments, bursts, time_tolerance);
+            evaluation.write(&mut stdout().lock()).unwrap();
+        });
+
+        (tx, handle)
+    }
+}
+
+/// Result of matching bursts to segments.
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    pub num_segments: usize,
+    pub num_bursts: usize,
+    pub num_matched: usize,
+    /// Relative size error (burst size - segment size) / segment size of matched pairs.
+    pub size_error: Summary,
+    /// Time (s) from segment request to the first packet of its matched burst.
+    pub timing_error: Summary,
+}
+
+impl Evaluation {
+    /// Match each segment, in time order, to the unmatched burst of the same flow
+    /// starting closest in time and within `time_tolerance` seconds of the request.
+    ///
+    /// Only bursts on flows that could match at least one segment are counted, so that
+    /// unrelated traffic does not affect precision.
+    pub fn new(mut segments: Vec<Segment>, bursts: Vec<Burst>, time_tolerance: f64) -> Self {
+        segments.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let mut bursts: Vec<Burst> = bursts
+            .into_iter()
+            .filter(|burst| segments.iter().any(|s| s.matches_flow(burst)))
+            .collect();
+        bursts.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+        let mut matched = vec![false; bursts.len()];
+        let mut size_errors = Vec::new();
+        let mut timing_errors = Vec::new();
+
+        for segment in &segments {
+            let candidate = bursts
+                .iter()
+                .enumerate()
+                .filter(|(i, burst)| {
+                    !matched[*i]
+                        && (burst.start - segment.time).abs() <= time_tolerance
+                        && segment.matches_flow(burst)
+                })
+                .min_by(|(_, a), (_, b)| {
+                    let a = (a.start - segment.time).abs();
+                    let b = (b.start - segment.time).abs();
+                    a.total_cmp(&b)
+                });
+
+            if let Some((i, burst)) = candidate {
+                matched[i] = true;
+                size_errors.push((burst.size as f64 - segment.size as f64) / segment.size as f64);
+                timing_errors.push(burst.start - segment.time);
+            }
+        }
+
+        Evaluation {
+            num_segments: segments.len(),
+            num_bursts: bursts.len(),
+            num_matched: size_errors.len(),
+            size_error: Summary::new(size_errors),
+            timing_error: Summary::new(timing_errors),
+        }
+    }
+
+    pub fn precision(&self) -> f64 {
+        self.num_matched as f64 / self.num_bursts as f64
+    }
+
+    pub fn recall(&self) -> f64 {
+        self.num_matched as f64 / self.num_segments as f64
+    }
+
+    pub fn write(&self, w: &mut impl Write) -> std::io::Result<()> {
+        writeln!(w, "segments   {}", self.num_segments)?;
+        writeln!(w, "bursts     {}", self.num_bursts)?;
+        writeln!(w, "matched    {}", self.num_matched)?;
+        writeln!(w, "precision  {:.4}", self.precision())?;
+        writeln!(w, "recall     {:.4}", self.recall())?;
+        writeln!(w)?;
+        writeln!(
+            w,
+            "{:16} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "", "mean", "min", "p5", "p25", "median", "p75", "p95", "max"
+        )?;
+        writeln!(
+            w,
+            "{:16} {}",
+            "size error (%)",
+            self.size_error.scaled(100.0)
+        )?;
+        writeln!(w, "{:16} {}", "timing error (s)", self.timing_error)
+    }
+}
+
+/// Distribution of a set of errors. All values are NaN if the set is empty.
+#[derive(Debug, Clone, Copy)]
+pub struct Summary {
+    pub mean: f64,
+    pub min: f64,
+    pub p5: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+impl Summary {
+    pub fn new(mut values: Vec<f64>) -> Self {
+        values.sort_by(f64::total_cmp);
+
+        // Nearest-rank percentile.
+        let percentile = |p: f64| -> f64 {
+            if values.is_empty() {
+                return f64::NAN;
+            }
+            let rank = (p / 100.0 * values.len() as f64).ceil() as usize;
+            values[rank.clamp(1, values.len()) - 1]
+        };
+
+        Summary {
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            min: percentile(0.0),
+            p5: percentile(5.0),
+            p25: percentile(25.0),
+            median: percentile(50.0),
+            p75: percentile(75.0),
+            p95: percentile(95.0),
+            max: percentile(100.0),
+        }
+    }
+
+    fn scaled(&self, factor: f64) -> Self {
+        Summary {
+            mean: self.mean * factor,
+            min: self.min * factor,
+            p5: self.p5 * factor,
+            p25: self.p25 * factor,
+            median: self.median * factor,
+            p75: self.p75 * factor,
+            p95: self.p95 * factor,
+            max: self.max * factor,
+        }
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:>10.4} {:>10.4} {:>10.4} {:>10.4} {:>10.4} {:>10.4} {:>10.4} {:>10.4}",
+            self.mean, self.min, self.p5, self.p25, self.median, self.p75, self.p95, self.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(name: &str, contents: &str) -> Result<Vec<Segment>, String> {
+        let path =
+            std::env::temp_dir().join(format!("burstshark-test-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        let segments = read_segments(path.to_str().unwrap()).map_err(|err| err.to_string());
+        std::fs::remove_file(&path).ok();
+        segments
+    }
+
+    #[test]
+    fn reads_csv_and_json_segments() {
+        let csv = read(
+            "segments.csv",
+            "time, size, dst_port\n1.5, 1000, 443\n2.5, 2000,\n",
+        );
+        let segments = csv.unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].size, 1000);
+        assert_eq!(segments[0].dst_port, Some(443));
+        assert_eq!(segments[1].dst_port, None);
+
+        let json = read(
+            "segments.json",
+            r#"[{"time": 1.5, "size": 1000, "src": "10.0.0.1"}]"#,
+        );
+        let segments = json.unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].src.as_deref(), Some("10.0.0.1"));
+    }
+
+    #[test]
+    fn rejects_segments_of_size_0() {
+        let csv = read("empty.csv", "time,size\n1.5,1000\n2.5,0\n");
+        assert_eq!(csv.unwrap_err(), "segment 2 at time 2.5 has a size of 0");
+
+        let json = read("empty.json", r#"[{"time": 1.5, "size": 0}]"#);
+        assert_eq!(json.unwrap_err(), "segment 1 at time 1.5 has a size of 0");
+    }
+}
@@ -0,0 +1,719 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::capture::{Burst, Direction};
+
+/// A filter expression on bursts, e.g. `bytes > 500k and dst_port == 443`.
+///
+/// Comparisons are combined with `and`, `or`, `not` and parentheses. Numbers may have a
+/// k, M or G suffix for thousands, millions and billions. Text is a bare word or quoted
+/// with double quotes. `in` tests membership of a network, e.g. `src in 10.0.0.0/8`, or
+/// of a parenthesized list, e.g. `dst_port in (80, 443)`. A comparison with a field the
+/// burst does not have, such as a WLAN field of an IP burst, is false.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    root: Node,
+}
+
+impl Expression {
+    pub fn matches(&self, burst: &Burst) -> bool {
+        self.root.matches(burst)
+    }
+}
+
+impl FromStr for Expression {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            pos: 0,
+        };
+        let root = parser.or()?;
+        match parser.next() {
+            None => Ok(Expression { root }),
+            Some(token) => Err(format!("unexpected {}", token)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Compare(Field, Op, Value),
+    In(Field, Vec<Value>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(f64),
+    Text(String),
+    /// An address and prefix length, a single address if the prefix is the full length.
+    Network(IpAddr, u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Number,
+    Text,
+    Address,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Src,
+    Dst,
+    /// Source or destination address.
+    Addr,
+    SrcPort,
+    DstPort,
+    /// Source or destination port.
+    Port,
+    Start,
+    End,
+    Duration,
+    Bytes,
+    Packets,
+    Rate,
+    Interface,
+    Probe,
+    Bssid,
+    Transmitter,
+    Receiver,
+    Direction,
+    Signal,
+    MinSignal,
+    Mcs,
+    DataRate,
+    Channel,
+    Retries,
+    EstimatedPackets,
+    EstimatedBytes,
+    RawBytes,
+}
+
+/// Names of the fields, with the alternative names of the burst attributes.
+const FIELDS: &[(&str, Field)] = &[
+    ("src", Field::Src),
+    ("dst", Field::Dst),
+    ("addr", Field::Addr),
+    ("src_port", Field::SrcPort),
+    ("dst_port", Field::DstPort),
+    ("port", Field::Port),
+    ("start", Field::Start),
+    ("end", Field::End),
+    ("duration", Field::Duration),
+    ("bytes", Field::Bytes),
+    ("size", Field::Bytes),
+    ("packets", Field::Packets),
+    ("num_packets", Field::Packets),
+    ("rate", Field::Rate),
+    ("throughput", Field::Rate),
+    ("interface", Field::Interface),
+    ("probe", Field::Probe),
+    ("bssid", Field::Bssid),
+    ("transmitter", Field::Transmitter),
+    ("receiver", Field::Receiver),
+    ("direction", Field::Direction),
+    ("signal", Field::Signal),
+    ("mean_signal", Field::Signal),
+    ("min_signal", Field::MinSignal),
+    ("mcs", Field::Mcs),
+    ("dominant_mcs", Field::Mcs),
+    ("data_rate", Field::DataRate),
+    ("mean_data_rate", Field::DataRate),
+    ("channel", Field::Channel),
+    ("retry_fraction", Field::Retries),
+    ("estimated_packets", Field::EstimatedPackets),
+    ("num_estimated", Field::EstimatedPackets),
+    ("estimated_bytes", Field::EstimatedBytes),
+    ("estimated_size", Field::EstimatedBytes),
+    ("raw_bytes", Field::RawBytes),
+    ("raw_size", Field::RawBytes),
+];
+
+/// Value of a field of a burst, borrowed from the burst where possible.
+enum FieldValue<'a> {
+    Number(f64),
+    Text(&'a str),
+}
+
+impl Field {
+    fn kind(self) -> Kind {
+        match self {
+            Field::Src | Field::Dst | Field::Addr => Kind::Address,
+            Field::Interface
+            | Field::Probe
+            | Field::Bssid
+            | Field::Transmitter
+            | Field::Receiver
+            | Field::Direction => Kind::Text,
+            _ => Kind::Number,
+        }
+    }
+
+    /// Values of the field for a burst. Fields of either address or port have two.
+    fn values(self, burst: &Burst) -> [Option<FieldValue<'_>>; 2] {
+        let wlan = burst.wlan.as_ref();
+        fn number<'a>(value: Option<f64>) -> Option<FieldValue<'a>> {
+            value.map(FieldValue::Number)
+        }
+        fn text(value: Option<&str>) -> Option<FieldValue<'_>> {
+            value.map(FieldValue::Text)
+        }
+
+        let value = match self {
+            Field::Src => text(Some(&burst.src)),
+            Field::Dst => text(Some(&burst.dst)),
+            Field::Addr => return [text(Some(&burst.src)), text(Some(&burst.dst))],
+            Field::SrcPort => number(Some(burst.src_port as f64)),
+            Field::DstPort => number(Some(burst.dst_port as f64)),
+            Field::Port => {
+                return [
+                    number(Some(burst.src_port as f64)),
+                    number(Some(burst.dst_port as f64)),
+                ]
+            }
+            Field::Start => number(Some(burst.start)),
+            Field::End => number(Some(burst.end)),
+            Field::Duration => number(Some(burst.duration())),
+            Field::Bytes => number(Some(burst.size as f64)),
+            Field::Packets => number(Some(burst.num_packets as f64)),
+            Field::Rate => number(burst.rate()),
+            Field::Interface => text(burst.interface.as_deref()),
+            Field::Probe => text(burst.probe.as_deref()),
+            Field::Bssid => text(wlan.and_then(|w| w.bssid.as_deref())),
+            Field::Transmitter => text(wlan.map(|w| w.transmitter.as_str())),
+            Field::Receiver => text(wlan.map(|w| w.receiver.as_str())),
+            Field::Direction => text(wlan.and_then(|w| w.direction).map(|d| match d {
+                Direction::Uplink => "uplink",
+                Direction::Downlink => "downlink",
+            })),
+            Field::Signal => number(wlan.and_then(|w| w.radio.mean_signal)),
+            Field::MinSignal => number(wlan.and_then(|w| w.radio.min_signal).map(f64::from)),
+            Field::Mcs => number(wlan.and_then(|w| w.radio.dominant_mcs).map(f64::from)),
+            Field::DataRate => number(wlan.and_then(|w| w.radio.mean_data_rate)),
+            Field::Channel => number(wlan.and_then(|w| w.radio.channel).map(f64::from)),
+            Field::Retries => number(wlan.map(|w| w.radio.retry_fraction)),
+            Field::EstimatedPackets => number(wlan.map(|w| w.num_estimated as f64)),
+            Field::EstimatedBytes => number(wlan.map(|w| w.estimated_size as f64)),
+            Field::RawBytes => number(wlan.map(|w| w.raw_size as f64)),
+        };
+
+        [value, None]
+    }
+}
+
+impl Node {
+    fn matches(&self, burst: &Burst) -> bool {
+        match self {
+            Node::And(left, right) => left.matches(burst) && right.matches(burst),
+            Node::Or(left, right) => left.matches(burst) || right.matches(burst),
+            Node::Not(node) => !node.matches(burst),
+            // Not equal if present and not equal to any value, e.g. neither port.
+            Node::Compare(field, Op::Ne, value) => {
+                let values = field.values(burst);
+                values.iter().any(Option::is_some)
+                    && !values.iter().flatten().any(|v| compare(v, Op::Eq, value))
+            }
+            Node::Compare(field, op, value) => field
+                .values(burst)
+                .iter()
+                .flatten()
+                .any(|v| compare(v, *op, value)),
+            Node::In(field, values) => field
+                .values(burst)
+                .iter()
+                .flatten()
+                .any(|v| values.iter().any(|value| compare(v, Op::Eq, value))),
+        }
+    }
+}
+
+fn compare(field: &FieldValue, op: Op, value: &Value) -> bool {
+    match (field, value) {
+        (FieldValue::Number(a), Value::Number(b)) => match op {
+            Op::Eq => a == b,
+            Op::Ne => a != b,
+            Op::Lt => a < b,
+            Op::Le => a <= b,
+            Op::Gt => a > b,
+            Op::Ge => a >= b,
+        },
+        // Text is only compared for equality, and MAC addresses may be in either case.
+        (FieldValue::Text(a), Value::Text(b)) => a.eq_ignore_ascii_case(b),
+        (FieldValue::Text(a), Value::Network(network, prefix)) => a
+            .parse::<IpAddr>()
+            .is_ok_and(|addr| in_network(addr, *network, *prefix)),
+        _ => false,
+    }
+}
+
+fn in_network(addr: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(addr) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(addr) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Comma,
+    Op(Op),
+    Word(String),
+    Quoted(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+            Token::Op(op) => write!(
+                f,
+                "{}",
+                match op {
+                    Op::Eq => "==",
+                    Op::Ne => "!=",
+                    Op::Lt => "<",
+                    Op::Le => "<=",
+                    Op::Gt => ">",
+                    Op::Ge => ">=",
+                }
+            ),
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Quoted(text) => write!(f, "\"{}\"", text),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            '=' | '!' | '<' | '>' => {
+                let equals = chars.next_if_eq(&'=').is_some();
+                Token::Op(match (c, equals) {
+                    ('=', true) => Op::Eq,
+                    ('!', true) => Op::Ne,
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    ('>', false) => Op::Gt,
+                    ('>', true) => Op::Ge,
+                    _ => return Err(format!("unknown operator {}, expected {}=", c, c)),
+                })
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => text.push(c),
+                        None => return Err("unterminated quote".to_string()),
+                    }
+                }
+                Token::Quoted(text)
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| !is_delimiter(*c)) {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "(),=!<>\"".contains(c)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Word(word)) if word == keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut node = self.and()?;
+        while self.keyword("or") {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut node = self.not()?;
+        while self.keyword("and") {
+            node = Node::And(Box::new(node), Box::new(self.not()?));
+        }
+        Ok(node)
+    }
+
+    fn not(&mut self) -> Result<Node, String> {
+        if self.keyword("not") {
+            return Ok(Node::Not(Box::new(self.not()?)));
+        }
+        if self.peek() == Some(&Token::Open) {
+            self.pos += 1;
+            let node = self.or()?;
+            return match self.next() {
+                Some(Token::Close) => Ok(node),
+                Some(token) => Err(format!("expected ), got {}", token)),
+                None => Err("missing )".to_string()),
+            };
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        let name = match self.next() {
+            Some(Token::Word(name)) => name,
+            Some(token) => return Err(format!("expected a field, got {}", token)),
+            None => return Err("expected a field".to_string()),
+        };
+        let field = FIELDS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, field)| *field)
+            .ok_or_else(|| format!("unknown field {}", name))?;
+
+        match self.next() {
+            Some(Token::Op(op)) => {
+                if field.kind() != Kind::Number && !matches!(op, Op::Eq | Op::Ne) {
+                    return Err(format!("{} can only be compared with == and !=", name));
+                }
+                let value = self.value(field)?;
+                Ok(Node::Compare(field, op, value))
+            }
+            Some(Token::Word(word)) if word == "in" => {
+                let mut values = Vec::new();
+                if self.peek() == Some(&Token::Open) {
+                    self.pos += 1;
+                    loop {
+                        values.push(self.member(field)?);
+                        match self.next() {
+                            Some(Token::Comma) => continue,
+                            Some(Token::Close) => break,
+                            _ => return Err("expected , or ) in list".to_string()),
+                        }
+                    }
+                } else {
+                    values.push(self.member(field)?);
+                }
+                Ok(Node::In(field, values))
+            }
+            Some(token) => Err(format!(
+                "expected an operator after {}, got {}",
+                name, token
+            )),
+            None => Err(format!("expected an operator after {}", name)),
+        }
+    }
+
+    /// A value to compare a field with.
+    fn value(&mut self, field: Field) -> Result<Value, String> {
+        let text = match self.next() {
+            Some(Token::Word(text) | Token::Quoted(text)) => text,
+            Some(token) => return Err(format!("expected a value, got {}", token)),
+            None => return Err("expected a value".to_string()),
+        };
+
+        match field.kind() {
+            Kind::Number => parse_number(&text).map(Value::Number),
+            Kind::Text => Ok(Value::Text(text)),
+            // Addresses are compared as addresses, so that IPv6 addresses match however
+            // they are written.
+            Kind::Address => Ok(match text.parse::<IpAddr>() {
+                Ok(addr) => Value::Network(addr, full_prefix(addr)),
+                Err(_) => Value::Text(text),
+            }),
+        }
+    }
+
+    /// A member of a list or network of an in test.
+    fn member(&mut self, field: Field) -> Result<Value, String> {
+        if field.kind() != Kind::Address {
+            return self.value(field);
+        }
+
+        let text = match self.next() {
+            Some(Token::Word(text) | Token::Quoted(text)) => text,
+            _ => return Err("expected an address or network".to_string()),
+        };
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (text.as_str(), None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|_| format!("invalid address {}", addr))?;
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= full_prefix(addr))
+                .ok_or_else(|| format!("invalid prefix length {}", prefix))?,
+            None => full_prefix(addr),
+        };
+
+        Ok(Value::Network(addr, prefix))
+    }
+}
+
+fn full_prefix(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Parse a number with an optional k, M or G suffix.
+pub fn parse_number(text: &str) -> Result<f64, String> {
+    let (number, scale) = match text.chars().last() {
+        Some('k' | 'K') => (&text[..text.len() - 1], 1e3),
+        Some('M') => (&text[..text.len() - 1], 1e6),
+        Some('G') => (&text[..text.len() - 1], 1e9),
+        _ => (text, 1.0),
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite())
+        .map(|n| n * scale)
+        .ok_or_else(|| format!("invalid number {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn burst() -> Burst {
+        Burst {
+            src: "10.0.0.1".to_string(),
+            dst: "2001:db8::1".to_string(),
+            src_port: 443,
+            dst_port: 5000,
+            start: 1.0,
+            end: 2.0,
+            num_packets: 10,
+            size: 1_500_000,
+            interface: None,
+            probe: None,
+            wlan: None,
+        }
+    }
+
+    fn matches(expression: &str, burst: &Burst) -> bool {
+        expression
+            .parse::<Expression>()
+            .unwrap_or_else(|err| panic!("failed to parse {:?}: {}", expression, err))
+            .matches(burst)
+    }
+
+    fn error(expression: &str) -> String {
+        expression.parse::<Expression>().unwrap_err()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let burst = burst();
+        assert!(matches(
+            "packets == 10 or packets == 1 and bytes == 0",
+            &burst
+        ));
+        assert!(!matches(
+            "(packets == 10 or packets == 1) and bytes == 0",
+            &burst
+        ));
+        assert!(matches(
+            "bytes == 0 and packets == 1 or packets == 10",
+            &burst
+        ));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_and_or() {
+        let burst = burst();
+        assert!(!matches("not packets == 10 and packets == 10", &burst));
+        assert!(matches("not packets == 10 or packets == 10", &burst));
+        assert!(!matches("not (packets == 1 or packets == 10)", &burst));
+        assert!(matches("not not packets == 10", &burst));
+    }
+
+    #[test]
+    fn in_list() {
+        let burst = burst();
+        assert!(matches("dst_port in (80, 443, 5000)", &burst));
+        assert!(!matches("dst_port in (80, 443)", &burst));
+        assert!(matches("port in (80, 443)", &burst));
+        assert!(matches("dst_port in 5000", &burst));
+        assert!(matches("bytes in (1.5M)", &burst));
+        assert!(matches("src in (192.168.0.1, 10.0.0.1)", &burst));
+    }
+
+    #[test]
+    fn in_network() {
+        let burst = burst();
+        assert!(matches("src in 10.0.0.0/8", &burst));
+        assert!(!matches("src in 10.1.0.0/16", &burst));
+        assert!(matches("dst in 2001:db8::/32", &burst));
+        assert!(!matches("dst in 2001:db9::/32", &burst));
+    }
+
+    #[test]
+    fn in_network_with_full_or_empty_prefix() {
+        let burst = burst();
+        assert!(matches("src in 0.0.0.0/0", &burst));
+        assert!(matches("src in 10.0.0.1/32", &burst));
+        assert!(!matches("src in 10.0.0.2/32", &burst));
+        assert!(matches("dst in ::/0", &burst));
+        assert!(matches("dst in 2001:db8::1/128", &burst));
+        assert!(!matches("dst in 2001:db8::2/128", &burst));
+    }
+
+    #[test]
+    fn ipv4_and_ipv6_networks_do_not_match_each_other() {
+        let burst = burst();
+        assert!(!matches("src in ::/0", &burst));
+        assert!(!matches("dst in 0.0.0.0/0", &burst));
+        assert!(!matches("src in ::ffff:10.0.0.1/128", &burst));
+    }
+
+    #[test]
+    fn addresses_are_compared_as_addresses() {
+        let burst = burst();
+        assert!(matches("dst == 2001:0db8:0:0:0:0:0:1", &burst));
+        assert!(matches("addr == 10.0.0.1", &burst));
+        assert!(matches("addr == 2001:db8::1", &burst));
+    }
+
+    #[test]
+    fn comparison_with_missing_field_is_false() {
+        let burst = burst();
+        assert!(!matches("interface == eth0", &burst));
+        assert!(!matches("interface != eth0", &burst));
+        assert!(!matches("signal < -70", &burst));
+        assert!(!matches("signal >= -70", &burst));
+        assert!(!matches("channel != 36", &burst));
+        assert!(matches("not interface == eth0", &burst));
+    }
+
+    #[test]
+    fn not_equal_requires_neither_value_to_be_equal() {
+        let mut burst = burst();
+        assert!(!matches("port != 443", &burst));
+        assert!(matches("port != 80", &burst));
+        burst.interface = Some("eth0".to_string());
+        assert!(matches("interface != eth1", &burst));
+        assert!(!matches("interface != ETH0", &burst));
+    }
+
+    #[test]
+    fn numbers_with_suffixes() {
+        let burst = burst();
+        assert!(matches("bytes == 1500k", &burst));
+        assert!(matches("bytes == 1.5M", &burst));
+        assert!(matches("bytes < 0.002G", &burst));
+        assert!(matches("bytes > 1499K", &burst));
+        assert_eq!(parse_number("500k"), Ok(500e3));
+        assert_eq!(parse_number("2M"), Ok(2e6));
+        assert_eq!(parse_number("3G"), Ok(3e9));
+        assert_eq!(parse_number("1.5"), Ok(1.5));
+    }
+
+    #[test]
+    fn invalid_numbers() {
+        for text in ["", "k", "1m", "1kk", "inf", "NaN", "1T"] {
+            assert_eq!(
+                parse_number(text),
+                Err(format!("invalid number {}", text)),
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn error_messages() {
+        let cases = [
+            ("", "expected a field"),
+            ("foo == 1", "unknown field foo"),
+            ("bytes", "expected an operator after bytes"),
+            ("bytes bytes", "expected an operator after bytes, got bytes"),
+            ("bytes = 1", "unknown operator =, expected =="),
+            ("bytes >", "expected a value"),
+            ("bytes > )", "expected a value, got )"),
+            ("bytes > 1x", "invalid number 1x"),
+            ("src < 10.0.0.1", "src can only be compared with == and !="),
+            (
+                "interface > eth0",
+                "interface can only be compared with == and !=",
+            ),
+            ("(bytes > 1", "missing )"),
+            ("(bytes > 1 bytes", "expected ), got bytes"),
+            ("bytes > 1)", "unexpected )"),
+            ("== 1", "expected a field, got =="),
+            ("interface == \"eth0", "unterminated quote"),
+            ("dst_port in (80 443)", "expected , or ) in list"),
+            ("src in foo", "invalid address foo"),
+            ("src in 10.0.0.0/33", "invalid prefix length 33"),
+            ("dst in ::/129", "invalid prefix length 129"),
+            ("src in 10.0.0.0/x", "invalid prefix length x"),
+            ("src in ()", "expected an address or network"),
+        ];
+        for (expression, message) in cases {
+            assert_eq!(error(expression), message, "{:?}", expression);
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{stdout, BufReader, BufWriter, Write};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::capture::{Burst, FlowKey};
+use crate::output::BurstFilter;
+
+/// Maximum number of non-matching bursts, e.g. audio segments, allowed between two
+/// consecutive segments of a match.
+const MAX_SKIP: usize = 2;
+
+/// Seconds without bursts after which a flow and its runs are forgotten.
+const FLOW_TIMEOUT: f64 = 60.0;
+
+/// Segment sizes of one representation (quality level) of a title.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Representation {
+    pub title: String,
+    pub representation: String,
+    /// Bandwidth in bits/s as advertised by the manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<u64>,
+    /// Size in bytes of each segment in playback order.
+    pub segments: Vec<u64>,
+}
+
+/// Read a database from a JSON array of representations.
+pub fn read_database(path: &str) -> Result<Vec<Representation>, Box<dyn Error>> {
+    Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+}
+
+/// Write a database as a JSON array of representations.
+pub fn write_database(
+    writer: impl Write,
+    database: &[Representation],
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(writer);
+    serde_json::to_writer_pretty(&mut writer, database)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MatchOptions {
+    /// Relative deviation allowed between a burst and a segment size.
+    pub tolerance: f64,
+    /// Bytes a burst may exceed a segment size by in addition to the tolerance, to allow
+    /// for TLS and HTTP overhead.
+    pub overhead: u64,
+    /// Consecutive matched segments required before a match is reported.
+    pub min_matches: usize,
+}
+
+/// A flow whose recent bursts match consecutive segments of a title.
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub title: String,
+    /// Representation of the last matched segment. Earlier segments of the match may be
+    /// from other representations of the title.
+    pub representation: String,
+    /// Index of the last matched segment.
+    pub segment: usize,
+    /// Number of consecutive segments matched.
+    pub run: usize,
+    /// Between 0 and 1. The share of titles matching a run of at least the same length,
+    /// weighted by the run length.
+    pub confidence: f64,
+}
+
+/// Index of the segment sizes of all titles in a database.
+pub struct Matcher {
+    options: MatchOptions,
+    titles: Vec<String>,
+    representations: Vec<String>,
+    /// Segment size, title index, representation index and segment index sorted by size.
+    sizes: Vec<(u64, usize, usize, usize)>,
+    flows: HashMap<FlowKey, FlowState>,
+}
+
+#[derive(Default)]
+struct FlowState {
+    num_bursts: usize,
+    /// End of the last burst.
+    last_end: f64,
+    /// Run length and index of the last burst of runs ending in (title, segment).
+    runs: HashMap<(usize, usize), (usize, usize)>,
+}
+
+impl Matcher {
+    pub fn new(database: Vec<Representation>, options: MatchOptions) -> Self {
+        let mut titles = Vec::<String>::new();
+        let mut representations = Vec::new();
+        let mut sizes = Vec::new();
+
+        for (rep_idx, rep) in database.into_iter().enumerate() {
+            let title_idx = match titles.iter().position(|t| *t == rep.title) {
+                Some(idx) => idx,
+                None => {
+                    titles.push(rep.title);
+                    titles.len() - 1
+                }
+            };
+
+            sizes.extend(
+                rep.segments
+                    .iter()
+                    .enumerate()
+                    .map(|(seg_idx, &size)| (size, title_idx, rep_idx, seg_idx)),
+            );
+            representations.push(rep.representation);
+        }
+
+        sizes.sort_unstable();
+
+        Matcher {
+            options,
+            titles,
+            representations,
+            sizes,
+            flows: HashMap::new(),
+        }
+    }
+
+    /// Add a burst to its flow and return the best match ending with it, if any run is long
+    /// enough.
+    pub fn add_burst(&mut self, burst: &Burst) -> Option<Match> {
+        // Forget flows, including this one, that have been inactive for too long.
+        self.flows
+            .retain(|_, flow| burst.start - flow.last_end <= FLOW_TIMEOUT);
+
+        let key = burst.flow_key();
+        let flow = self.flows.entry(key).or_default();
+        flow.last_end = flow.last_end.max(burst.end);
+        let size = burst.size as u64;
+
+        // Segments a burst can be: seg * (1 - tol) <= size <= seg * (1 + tol) + overhead.
+        let tolerance = self.options.tolerance;
+        let lower = (size.saturating_sub(self.options.overhead) as f64 / (1.0 + tolerance)) as u64;
+        let upper = (size as f64 / (1.0 - tolerance).max(f64::EPSILON)) as u64;
+        let from = self.sizes.partition_point(|s| s.0 < lower);
+        let to = self.sizes.partition_point(|s| s.0 <= upper);
+
+        let burst_idx = flow.num_bursts;
+        flow.num_bursts += 1;
+
+        let mut new_runs = Vec::new();
+        for &(_, title, rep, seg) in &self.sizes[from..to] {
+            let run = seg
+                .checked_sub(1)
+                .and_then(|prev| flow.runs.get(&(title, prev)))
+                .filter(|(_, last)| burst_idx - last <= MAX_SKIP + 1)
+                .map_or(1, |(run, _)| run + 1);
+            new_runs.push((title, rep, seg, run));
+        }
+
+        flow.runs
+            .retain(|_, (_, last)| burst_idx - *last <= MAX_SKIP);
+        for &(title, _, seg, run) in &new_runs {
+            let entry = flow.runs.entry((title, seg)).or_insert((run, burst_idx));
+            if run >= entry.0 {
+                *entry = (run, burst_idx);
+            }
+        }
+
+        let &(title, rep, seg, run) = new_runs.iter().max_by_key(|(.., run)| *run)?;
+        if run < self.options.min_matches {
+            return None;
+        }
+
+        let mut competing: Vec<usize> = new_runs
+            .iter()
+            .filter(|(.., r)| *r >= run)
+            .map(|(t, ..)| *t)
+            .collect();
+        competing.sort_unstable();
+        competing.dedup();
+
+        Some(Match {
+            title: self.titles[title].clone(),
+            representation: self.representations[rep].clone(),
+            segment: seg,
+            run,
+            confidence: (1.0 - 0.5f64.powi(run as i32)) / competing.len() as f64,
+        })
+    }
+}
+
+/// Matches each flow's bursts against a database and writes matches to stdout as they
+/// occur.
+pub struct Fingerprinter {
+    database: Vec<Representation>,
+    options: MatchOptions,
+    filter: BurstFilter,
+}
+
+impl Fingerprinter {
+    pub fn new(database: Vec<Representation>, options: MatchOptions, filter: BurstFilter) -> Self {
+        Fingerprinter {
+            database,
+            options,
+            filter,
+        }
+    }
+
+    pub async fn start(&mut self) -> (mpsc::Sender<Burst>, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::channel::<Burst>(100);
+
+        let mut matcher = Matcher::new(self.database.clone(), self.options);
+        let filter = self.filter.clone();
+
+        let handle = tokio::spawn(async move {
+            let stdout = stdout();
+            let start_time = SystemTime::now();
+            let mut count = 0;
+
+            while let Some(burst) = rx.recv().await {
+                if !filter.matches(&burst) {
+                    continue;
+                }
+
+                let Some(m) = matcher.add_burst(&burst) else {
+                    continue;
+                };
+
+                count += 1;
+
+                let elapsed = start_time.elapsed().unwrap_or_default().as_secs_f64();
+
+                let mut handle = stdout.lock();
+
+                writeln!(
+                    &mut handle,
+                    "{:5} {:13.9} {:15} {:6} {:15} {:5} {:13.9} {:5} {:4} {:6.4} {} {}",
+                    count,
+                    elapsed,
+                    burst.src,
+                    burst.src_port,
+                    burst.dst,
+                    burst.dst_port,
+                    burst.end,
+                    m.segment,
+                    m.run,
+                    m.confidence,
+                    m.title,
+                    m.representation,
+                )
+                .unwrap();
+            }
+        });
+
+        (tx, handle)
+    }
+}
@@ -1,4 +1,20 @@
 #![forbid(unsafe_code)]
 
+pub mod annotate;
 pub mod capture;
+pub mod columnar;
+pub mod config;
+pub mod evaluate;
+pub mod expression;
+pub mod fingerprint;
+pub mod manifest;
+pub mod metrics;
 pub mod output;
+pub mod pcap;
+pub mod publish;
+pub mod remote;
+pub mod session;
+pub mod sink;
+pub mod source;
+pub mod sqlite;
+pub mod tui;
@@ -1,24 +1,51 @@
 use std::error::Error;
-
-use clap::Parser;
-
-use burstshark::capture::{CaptureType, CommonOptions};
-use burstshark::output::OutputWriter;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{stdout, IsTerminal};
+use std::sync::Arc;
+
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+
+use burstshark::annotate::Annotations;
+use burstshark::capture::{CaptureType, CommonOptions, Estimator};
+use burstshark::columnar::ColumnarWriter;
+use burstshark::config;
+use burstshark::evaluate::{read_segments, Evaluator};
+use burstshark::expression::{parse_number, Expression};
+use burstshark::fingerprint::{read_database, write_database, Fingerprinter, MatchOptions};
+use burstshark::manifest::read_manifest;
+use burstshark::metrics::{serve, Metrics};
+use burstshark::output::{reorder, tee, BurstFilter, OutputSpec, OutputWriter};
+use burstshark::publish::Publisher;
+use burstshark::remote::send;
+use burstshark::session::SessionOptions;
+use burstshark::sink::HttpSink;
+use burstshark::source::{expand_paths, is_monitor_interface, is_stream, Source};
+use burstshark::sqlite::SqliteWriter;
+use burstshark::tui::Dashboard;
 
 #[derive(Parser, Clone, Debug)]
 #[clap(author, version, about)]
 struct Args {
     /// Network interface to use for live capture.
     ///
-    /// Uses first non-loopback interface if no interface or file supplied.
+    /// Uses first non-loopback interface if no interface or file supplied. Can be given
+    /// multiple times to capture on several interfaces, in which case flows are kept
+    /// separate per interface and each burst is tagged with its interface. Bursts are then
+    /// output in the order they ended, up to twice the burst timeout later. All interfaces
+    /// must be wired, or all in monitor mode with --wlan.
     #[clap(short = 'i', long = "interface")]
-    interface: Option<String>,
+    interfaces: Vec<String>,
 
     /// Packet filter for live capture in libpcap filter syntax.
     ///
     /// Merged with a default filter that captures UDP and TCP packets with payload,
-    /// or QoS data WLAN frames if WLAN is enabled.
-    #[clap(short = 'f', long = "capture-filter", conflicts_with = "infile")]
+    /// or QoS data and Block Ack WLAN frames if WLAN is enabled.
+    #[clap(
+        short = 'f',
+        long = "capture-filter",
+        conflicts_with_all = ["infiles", "listen"]
+    )]
     capture_filter: Option<String>,
 
     /// Number of bytes to capture per packet during live capture.
@@ -30,15 +57,57 @@ struct Args {
         short = 's',
         long = "snapshot-length",
         default_value_t = 96,
-        conflicts_with = "infile"
+        conflicts_with_all = ["infiles", "listen"]
     )]
     snaplen: u32,
 
     /// Read packet data from infile.
     ///
-    /// Can be any capture file format supported by tshark, including gzipped files.
-    #[clap(short = 'r', long = "read-file", conflicts_with = "interface")]
-    infile: Option<String>,
+    /// Can be any capture file format supported by tshark, including gzipped files. Can
+    /// be given multiple times, and can be a directory or a quoted glob pattern, e.g.
+    /// 'ring/*.pcapng', to read several files. Packets of all files are merged in
+    /// timestamp order and flows continue across files.
+    ///
+    /// Use - to read a pcap or pcapng stream from standard input, e.g. from tcpdump -U -w -.
+    /// Named pipes are also read as streams.
+    #[clap(short = 'r', long = "read-file", conflicts_with = "interfaces")]
+    infiles: Vec<String>,
+
+    /// Follow the newest pcap or pcapng file of the infiles as it grows, like tail -F.
+    ///
+    /// Switches to a newer file as soon as one is created, e.g. by a dumpcap ring buffer,
+    /// or when the file is replaced. Only packets written after BurstShark starts are read.
+    #[clap(short = 'F', long = "follow", requires = "infiles")]
+    follow: bool,
+
+    /// Collect pcap or pcapng streams from remote probes connecting over TCP to addr.
+    ///
+    /// Each probe's packets have their own flows, and bursts are tagged with the ID of
+    /// the probe. A stream may be preceded by a line "PROBE <id>" to identify the probe,
+    /// otherwise it is identified by its IP address. Probes can send with --send, or
+    /// e.g. with tcpdump -U -w - piped to nc.
+    #[clap(
+        short = 'L',
+        long = "listen",
+        value_name = "ADDR",
+        conflicts_with_all = ["interfaces", "infiles"]
+    )]
+    listen: Option<String>,
+
+    /// Run as a probe, sending the live capture to a collector listening at addr.
+    ///
+    /// Packets are captured with the capture filter and snapshot length, and sent as a
+    /// pcapng stream instead of being analyzed.
+    #[clap(
+        long = "send",
+        value_name = "ADDR",
+        conflicts_with_all = ["infiles", "listen", "pcap_outfile"]
+    )]
+    send: Option<String>,
+
+    /// ID of this probe when sending to a collector.
+    #[clap(long = "probe-id", value_name = "ID", requires = "send")]
+    probe_id: Option<String>,
 
     /// Packet filter in Wireshark display filter syntax.
     ///
@@ -48,7 +117,8 @@ struct Args {
     #[clap(short = 'Y', long = "display-filter")]
     display_filter: Option<String>,
 
-    /// Seconds with no flow activity for a burst to be considered complete.
+    /// Seconds with no flow activity for a burst to be considered complete. Must be
+    /// greater than 0.
     #[clap(short = 't', long = "burst_timeout", default_value_t = 0.5)]
     burst_timeout: f64,
 
@@ -59,9 +129,26 @@ struct Args {
     aggregate_ports: bool,
 
     /// Write raw packet data read by tshark to pcap_outfile.
-    #[clap(short = 'w', long = "write-pcap")]
+    ///
+    /// Cannot be used when reading more than one file or collecting from remote probes, as
+    /// each file and probe is read by its own tshark process.
+    #[clap(short = 'w', long = "write-pcap", conflicts_with = "listen")]
     pcap_outfile: Option<String>,
 
+    /// Comment each packet of a burst in pcap_outfile with its flow and burst.
+    ///
+    /// The file is written as pcapng and annotated once the capture has ended. Comments
+    /// read e.g. "burstshark flow 3 burst 17: 10.0.0.1:443 -> 10.0.0.2:5000, 312 packets,
+    /// 452103 bytes, start 1681222001.012345678", and can be filtered on in Wireshark with
+    /// frame.comment. Which packets belong to which burst is kept in memory until then,
+    /// growing with the length of the capture.
+    #[clap(
+        long = "annotate",
+        requires = "pcap_outfile",
+        conflicts_with = "listen"
+    )]
+    annotate: bool,
+
     /// Only display bursts with a minimum size of min_bytes.
     #[clap(short = 'b', long = "min-bytes")]
     min_bytes: Option<u32>,
@@ -78,39 +165,299 @@ struct Args {
     #[clap(short = 'P', long = "max-packets")]
     max_packets: Option<u16>,
 
+    /// Only display bursts lasting at least min_duration seconds from the first to the
+    /// last packet.
+    #[clap(long = "min-duration", value_name = "SECONDS")]
+    min_duration: Option<f64>,
+
+    /// Only display bursts lasting at most max_duration seconds from the first to the last
+    /// packet.
+    #[clap(long = "max-duration", value_name = "SECONDS")]
+    max_duration: Option<f64>,
+
+    /// Only display bursts with an average throughput of at least min_rate bits/s.
+    ///
+    /// The rate may have a k, M or G suffix, e.g. 2M. Bursts whose packets all have the
+    /// same timestamp, such as bursts of a single packet, have no throughput and are not
+    /// displayed.
+    #[clap(long = "min-rate", value_name = "BITS/S", value_parser = parse_number)]
+    min_rate: Option<f64>,
+
+    /// Only display bursts matching a filter expression.
+    ///
+    /// Comparisons of burst fields, e.g. bytes > 500k and duration < 2 and
+    /// dst_port == 443 and src in 10.0.0.0/8, combined with and, or, not and parentheses.
+    /// See the README for the fields.
+    #[clap(long = "filter", value_name = "EXPR")]
+    expression: Option<Expression>,
+
     /// Read 802.11 WLAN QoS data frames instead of IP packets.
     ///
-    /// For live capture, the interface should be in monitor mode.
+    /// Sequence numbers are tracked per TID, and Block Ack frames are used to confirm
+    /// which missed frames were delivered. Frame sizes exclude protection and LLC/SNAP
+    /// headers to estimate the size of the IP packets. For live capture, the interface
+    /// should be in monitor mode.
     #[clap(short = 'I', long = "wlan")]
     wlan: bool,
 
     /// Disable frame size estimation for missed WLAN frames.
     ///
     /// By default, missed WLAN frames will have their sizes estimated based on the
-    /// average size of the frames captured.
+    /// sizes of the frames captured.
     #[clap(short = 'E', long = "no-estimation", requires = "wlan")]
     no_estimation: bool,
 
+    /// Method of estimating the size of missed WLAN frames.
+    #[clap(
+        short = 'e',
+        long = "estimator",
+        value_enum,
+        default_value_t = Estimator::Mean,
+        requires = "wlan",
+        conflicts_with = "no_estimation"
+    )]
+    estimator: Estimator,
+
     /// Maximum allowed deviation from the expected WLAN sequence number.
     ///
-    /// Only frames within max_deviation will be considered and estimated.
+    /// Only frames within max_deviation will be considered and estimated. At most 2048,
+    /// half of the sequence number space.
     #[clap(
         short = 'M',
         long = "max-deviation",
         default_value_t = 200,
+        value_parser = clap::value_parser!(u16).range(0..=2048),
         requires = "wlan"
     )]
     max_deviation: u16,
 
+    /// Protected WLAN frames use GCMP or CCMP-256 instead of CCMP-128.
+    ///
+    /// Frame sizes exclude the protection header and MIC, which is 16 bytes instead of 8
+    /// with these ciphers.
+    #[clap(short = 'G', long = "gcmp", requires = "wlan")]
+    gcmp: bool,
+
+    /// Only consider WLAN frames of the network with this BSSID.
+    ///
+    /// Can be given multiple times to consider several networks.
+    #[clap(short = 'S', long = "bssid", requires = "wlan")]
+    bssids: Vec<String>,
+
+    /// Also write bursts to an output given as format:path, with its own filters.
+    ///
+    /// Format is text, jsonl, parquet or arrow (Arrow IPC), and path is a file or - for
+    /// standard output, which then replaces the default output. Parquet and arrow outputs
+    /// must be files. Filters follow as comma-separated key=value pairs with the keys
+    /// min-bytes, max-bytes, min-packets, max-packets, min-duration, max-duration, min-rate
+    /// and filter, a filter expression, e.g. jsonl:bursts.jsonl,min-bytes=100000,min-rate=1M.
+    /// The filter options above only apply to the default output. Can be given multiple
+    /// times.
+    #[clap(
+        short = 'o',
+        long = "output",
+        value_name = "FORMAT:PATH",
+        conflicts_with = "send"
+    )]
+    outputs: Vec<OutputSpec>,
+
+    /// Insert bursts into an SQLite database at path, in addition to the output.
+    ///
+    /// The database is created if it does not exist. Each run adds a row with its start
+    /// and end time, command line, effective options and tshark arguments to the runs
+    /// table, its flows to the flows table and their bursts to the bursts table.
+    #[clap(long = "sqlite", value_name = "PATH", conflicts_with = "send")]
+    sqlite: Option<String>,
+
+    /// Bursts per row group of parquet outputs, and per record batch of arrow outputs.
+    #[clap(long = "row-group-size", value_name = "ROWS", default_value_t = 65536)]
+    row_group_size: usize,
+
+    /// Show a live dashboard of the busiest flows instead of printing bursts.
+    ///
+    /// Shows the throughput, and for each flow its recent bursts and a sparkline of their
+    /// sizes. The dashboard can be paused, and the flows filtered by address, port, probe
+    /// or interface. Quitting the dashboard stops the capture. Messages from tshark and
+    /// BurstShark are shown in the dashboard unless standard error is redirected.
+    #[clap(long = "tui", conflicts_with = "send")]
+    tui: bool,
+
+    /// Publish bursts as JSON lines to clients connecting to addr, in addition to the output.
+    ///
+    /// Addr is a TCP address, e.g. 127.0.0.1:7000, or the path of a Unix domain socket if
+    /// it contains a /. Clients receive all bursts until they send a filter as a JSON object
+    /// on a single line, e.g. {"min_bytes": 100000, "port": 443}.
+    #[clap(long = "publish", value_name = "ADDR", conflicts_with = "send")]
+    publish: Option<String>,
+
+    /// Bursts queued for each publish client before its oldest bursts are dropped.
+    #[clap(
+        long = "publish-buffer",
+        value_name = "BURSTS",
+        default_value_t = 1000,
+        requires = "publish"
+    )]
+    publish_buffer: usize,
+
+    /// Post bursts in batches, as JSON arrays, to an HTTP collector at url, in addition to
+    /// the output.
+    ///
+    /// Batches that cannot be delivered are retried with exponential backoff, up to a
+    /// minute apart.
+    #[clap(long = "sink", value_name = "URL", conflicts_with = "send")]
+    sink: Option<String>,
+
+    /// Bursts per batch posted to the sink.
+    #[clap(
+        long = "sink-batch-size",
+        value_name = "BURSTS",
+        default_value_t = 100,
+        requires = "sink"
+    )]
+    sink_batch_size: usize,
+
+    /// Seconds after which a batch is posted to the sink even if it is not full.
+    #[clap(
+        long = "sink-interval",
+        value_name = "SECONDS",
+        default_value_t = 5.0,
+        requires = "sink"
+    )]
+    sink_interval: f64,
+
+    /// Keep batches not yet delivered to the sink in dir.
+    ///
+    /// Batches survive restarts and are sent before new batches. Without a spool
+    /// directory, undelivered batches are kept in memory and lost on exit.
+    #[clap(long = "sink-spool", value_name = "DIR", requires = "sink")]
+    sink_spool: Option<String>,
+
+    /// Serve Prometheus metrics over HTTP at addr, e.g. 0.0.0.0:9090.
+    ///
+    /// Exposes counters of parsed packets, parse errors, emitted and filtered bursts,
+    /// the number of active flows, histograms of burst sizes and durations, and how
+    /// often flows wait for the output, at /metrics.
+    #[clap(long = "metrics-addr", value_name = "ADDR", conflicts_with = "send")]
+    metrics_addr: Option<String>,
+
+    /// Read options from a TOML configuration file.
+    ///
+    /// Keys are long option names, e.g. burst_timeout = 0.5 or display-filter = "tcp".
+    /// Flags are set with true, and options given more than once with an array. Options
+    /// given on the command line override those in the file, except that a flag set in the
+    /// file cannot be unset on the command line. Commands and their options, and the
+    /// filter argument, can only be given on the command line.
+    #[clap(long = "config", value_name = "PATH")]
+    config: Option<String>,
+
+    /// Use the values of [profile.NAME] in the configuration file over the top-level ones.
+    #[clap(long = "profile", value_name = "NAME", requires = "config")]
+    profile: Option<String>,
+
+    /// Print the effective configuration as TOML and exit.
+    #[clap(long = "print-config")]
+    print_config: bool,
+
     #[clap(value_delimiter=' ', hide(true), conflicts_with_all(["capture_filter", "display_filter"]))]
     positional_filter: Option<Vec<String>>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
 }
 
-fn tshark_args(args: Args) -> Vec<String> {
-    let mut tshark_args = vec!["-l", "-q", "-n", "-T", "fields"];
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Evaluate detected bursts against a ground-truth log of media segments.
+    ///
+    /// Each segment is matched to the closest burst in time on the same flow. Reports
+    /// precision, recall and the distribution of size and timing errors once the capture
+    /// has ended.
+    Evaluate {
+        /// CSV or JSON file of segments with a request time and size.
+        ///
+        /// CSV files must have a header row and JSON files an array of objects, with the
+        /// fields time (Unix timestamp) and size (bytes). The optional fields src, dst,
+        /// src_port and dst_port restrict which flows a segment can be matched to.
+        #[clap(short = 'g', long = "ground-truth")]
+        ground_truth: String,
+
+        /// Maximum seconds between a segment request and the start of its burst.
+        #[clap(short = 'T', long = "time-tolerance", default_value_t = 1.0)]
+        time_tolerance: f64,
+    },
+
+    /// Group each flow's bursts into adaptive streaming playback sessions.
+    ///
+    /// Every burst is treated as a media segment. Outputs a line per segment with its
+    /// estimated bitrate, quality level, quality switches and the buffer state of the
+    /// session.
+    Session {
+        /// Playback duration of a segment in seconds.
+        ///
+        /// Inferred from the intervals between bursts of a session if not supplied.
+        #[clap(short = 'd', long = "segment-duration")]
+        segment_duration: Option<f64>,
+
+        /// Comma-separated bitrates (kbit/s) of the available quality levels.
+        ///
+        /// Segments are assigned the closest level. If not supplied, levels are formed from
+        /// the bitrates observed in each session.
+        #[clap(short = 'l', long = "bitrate-ladder", value_delimiter = ',')]
+        bitrate_ladder: Option<Vec<f64>>,
+    },
+
+    /// Identify streamed titles by matching burst sizes against a database of segment sizes.
+    ///
+    /// Outputs a line each time a flow's bursts match enough consecutive segments of a
+    /// title, with the index of the last matched segment, the number of matched segments
+    /// and a confidence score.
+    Fingerprint {
+        /// JSON database of segment sizes.
+        ///
+        /// An array of objects with the fields title, representation and segments, where
+        /// segments is an array of segment sizes in bytes in playback order.
+        #[clap(short = 'D', long = "database")]
+        database: String,
+
+        /// Relative deviation allowed between a burst and a segment size, between 0 and 1.
+        #[clap(short = 'T', long = "tolerance", default_value_t = 0.01)]
+        tolerance: f64,
+
+        /// Bytes a burst may exceed a segment size by, in addition to the tolerance.
+        ///
+        /// Allows for TLS and HTTP overhead.
+        #[clap(short = 'o', long = "overhead", default_value_t = 2000)]
+        overhead: u64,
+
+        /// Consecutive matched segments required before a match is output.
+        #[clap(short = 'm', long = "min-matches", default_value_t = 3)]
+        min_matches: usize,
+    },
+
+    /// Build a fingerprint database from DASH MPD and HLS playlist files.
+    ///
+    /// Computes the segment sizes of every representation from byte ranges in the manifest,
+    /// sidx boxes of media files or the sizes of segment files. All files are read from
+    /// disk, relative to the directory of the manifest.
+    BuildDatabase {
+        /// DASH MPD (.mpd) or HLS playlist (.m3u8) files.
+        #[clap(required = true)]
+        manifests: Vec<String>,
+
+        /// Title of the manifests. Defaults to the file name of each manifest.
+        #[clap(short = 'n', long = "title")]
+        title: Option<String>,
+
+        /// Write the database to outfile instead of stdout.
+        #[clap(short = 'o', long = "output")]
+        outfile: Option<String>,
+    },
+}
 
+/// Fields of each packet output by tshark.
+fn tshark_fields(args: &Args) -> Vec<&'static str> {
     #[rustfmt::skip]
-    tshark_args.extend(match args.wlan {
+    let mut fields = match args.wlan {
         false => vec![
             "-e", "frame.time_epoch",
             "-e", "ip.src",
@@ -131,16 +478,57 @@ fn tshark_args(args: Args) -> Vec<String> {
             "-e", "wlan.da",
             "-e", "data.len",
             "-e", "wlan.seq",
+            "-e", "wlan.qos.tid",
+            "-e", "wlan.ta",
+            "-e", "wlan.ra",
+            "-e", "wlan.ba.control.tidinfo",
+            "-e", "wlan.fixed.ssc.sequence",
+            "-e", "wlan.ba.bm",
+            "-e", "wlan.bssid",
+            "-e", "wlan.fc.ds",
+            "-e", "wlan_radio.signal_dbm",
+            "-e", "wlan_radio.data_rate",
+            "-e", "wlan_radio.mcs_index",
+            "-e", "wlan_radio.channel",
+            "-e", "wlan.fc.retry",
+            "-e", "wlan.fc.protected",
+            "-e", "wlan.wep.iv",
+            "-e", "wlan.tkip.extiv",
+            "-e", "wlan.ccmp.extiv",
         ],
-    });
+    };
+
+    if args.interfaces.len() > 1 {
+        fields.extend(["-e", "frame.interface_name"]);
+    }
+
+    fields
+}
+
+fn tshark_args(args: Args) -> Vec<String> {
+    let mut tshark_args = vec!["-l", "-q", "-n"];
+
+    match args.send {
+        // Probes send the capture itself to the collector.
+        Some(_) => tshark_args.extend(["-w", "-", "-F", "pcapng"]),
+        None => {
+            tshark_args.extend(["-T", "fields"]);
+            tshark_args.extend(tshark_fields(&args));
+        }
+    }
+
+    // Packets are read from capture files rather than captured.
+    let reading = !args.infiles.is_empty() || args.listen.is_some();
 
-    let base_filter = match (&args.infile, &args.wlan) {
-        (None, false) => String::from(
+    let base_filter = match (reading, &args.wlan) {
+        (false, false) => String::from(
             "udp or (tcp and (((ip[2:2] - ((ip[0]&0xf)<<2)) - ((tcp[12]&0xf0)>>2)) != 0))",
         ),
-        (None, true) => String::from("wlan type data subtype qos-data"),
-        (Some(_), false) => String::from("udp or (tcp and tcp.len > 0)"),
-        (Some(_), true) => String::from("wlan and wlan.fc.type_subtype == 40"),
+        (false, true) => {
+            String::from("wlan type data subtype qos-data or wlan type ctl subtype ba")
+        }
+        (true, false) => String::from("udp or (tcp and tcp.len > 0)"),
+        (true, true) => String::from("wlan.fc.type_subtype == 40 or wlan.fc.type_subtype == 25"),
     };
 
     let create_filter = |optional_filter: Option<String>| -> Option<String> {
@@ -150,9 +538,9 @@ fn tshark_args(args: Args) -> Vec<String> {
         }
     };
 
-    let (capture_filter, display_filter) = match &args.infile {
-        None => (create_filter(args.capture_filter), args.display_filter),
-        Some(_) => (None, create_filter(args.display_filter)),
+    let (capture_filter, display_filter) = match reading {
+        false => (create_filter(args.capture_filter), args.display_filter),
+        true => (None, create_filter(args.display_filter)),
     };
 
     let snapshot_length = args.snaplen.to_string();
@@ -165,38 +553,326 @@ fn tshark_args(args: Args) -> Vec<String> {
         tshark_args.extend(vec!["-Y", display_filter]);
     }
 
-    if let Some(interface) = &args.interface {
+    for interface in &args.interfaces {
         tshark_args.extend(vec!["-i", interface]);
     }
 
-    if let Some(infile) = &args.infile {
-        tshark_args.extend(vec!["-r", infile]);
-    }
-
     if let Some(pcap_outfile) = &args.pcap_outfile {
         tshark_args.extend(vec!["-w", pcap_outfile, "-P"]);
+        if args.annotate {
+            tshark_args.extend(["-F", "pcapng"]);
+        }
     }
 
     tshark_args.into_iter().map(str::to_string).collect()
 }
 
+/// Parse the command line, with options not given on it read from the configuration file.
+fn parse_args() -> Result<(Args, ArgMatches), Box<dyn Error>> {
+    let mut argv: Vec<OsString> = std::env::args_os().collect();
+
+    // The command line alone may not be valid, e.g. if an option it has requires one in the
+    // configuration file, so it is only validated once merged with the file.
+    let partial = Args::command().ignore_errors(true).get_matches_from(&argv);
+
+    if let Some(path) = partial.get_one::<String>("config") {
+        let profile = partial.get_one::<String>("profile");
+        let config = config::load(path, profile.map(String::as_str))?;
+        let config_args = config::to_args(&config, &Args::command(), &partial)
+            .map_err(|err| format!("failed to read config {}: {}", path, err))?;
+        argv.splice(1..1, config_args);
+    }
+
+    let matches = Args::command().get_matches_from(argv);
+    Ok((Args::from_arg_matches(&matches)?, matches))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let args: Args = Args::parse();
+    let (args, matches) = parse_args()?;
+
+    if args.print_config {
+        print!("{}", config::render(&Args::command(), &matches));
+        return Ok(());
+    }
+
+    if let Some(Command::BuildDatabase {
+        manifests,
+        title,
+        outfile,
+    }) = &args.command
+    {
+        let mut database = Vec::new();
+        for manifest in manifests {
+            database.extend(
+                read_manifest(manifest, title.as_deref())
+                    .map_err(|err| format!("failed to read {}: {}", manifest, err))?,
+            );
+        }
+
+        match outfile {
+            Some(outfile) => write_database(File::create(outfile)?, &database)?,
+            None => write_database(stdout(), &database)?,
+        }
+
+        return Ok(());
+    }
+
+    if let Some(addr) = &args.send {
+        if args.command.is_some() {
+            return Err("a probe only sends its capture and cannot run a command".into());
+        }
+        return send(addr, args.probe_id.as_deref(), &tshark_args(args.clone())).await;
+    }
+
+    if !args.burst_timeout.is_finite() || args.burst_timeout <= 0.0 {
+        return Err("burst timeout must be a positive number of seconds".into());
+    }
+
+    let source = match (&args.listen, args.infiles.as_slice(), args.follow) {
+        (Some(addr), ..) => Source::Listen(addr.clone()),
+        (None, [], _) => Source::Live,
+        (None, [path], false) if is_stream(path) => Source::Stream(path.clone()),
+        (None, paths, true) if paths.iter().any(|path| is_stream(path)) => {
+            return Err(
+                "cannot follow standard input or a named pipe, read it without --follow".into(),
+            );
+        }
+        (None, paths, _) if paths.iter().any(|path| is_stream(path)) => {
+            return Err("standard input or a named pipe must be the only infile".into());
+        }
+        (None, _, true) => Source::Follow(args.infiles.clone()),
+        (None, _, false) => Source::Files(expand_paths(&args.infiles)?),
+    };
+
+    // The capture filter and fields apply to all interfaces, and a WLAN capture filter
+    // cannot be compiled for a wired interface.
+    let (mut monitor, mut wired) = (None, None);
+    for interface in &args.interfaces {
+        match is_monitor_interface(interface) {
+            Some(true) => monitor = monitor.or(Some(interface)),
+            Some(false) => wired = wired.or(Some(interface)),
+            None => {}
+        }
+    }
+    if let (Some(monitor), Some(wired)) = (monitor, wired) {
+        return Err(format!(
+            "cannot capture on monitor mode interface {} and wired interface {} at once",
+            monitor, wired
+        )
+        .into());
+    }
+
+    if let Source::Files(paths) = &source {
+        if args.pcap_outfile.is_some() && paths.len() > 1 {
+            return Err("cannot write a pcap file when reading multiple files".into());
+        }
+    }
+
+    if args.tui {
+        if args.command.is_some() {
+            return Err("the dashboard only shows bursts and cannot run a command".into());
+        }
+        if !stdout().is_terminal() {
+            return Err("the dashboard requires a terminal".into());
+        }
+    }
+
+    if let Some(Command::Session {
+        segment_duration: Some(duration),
+        ..
+    }) = &args.command
+    {
+        if !duration.is_finite() || *duration <= 0.0 {
+            return Err("segment duration must be a positive number of seconds".into());
+        }
+    }
+
+    if let Some(Command::Fingerprint { tolerance, .. }) = &args.command {
+        if !(*tolerance > 0.0 && *tolerance < 1.0) {
+            return Err("fingerprint tolerance must be between 0 and 1".into());
+        }
+    }
+
+    let stdout_output = args.outputs.iter().any(OutputSpec::is_stdout);
+    if stdout_output && (args.tui || args.command.is_some()) {
+        return Err(
+            "an output cannot be written to standard output with --tui or a command".into(),
+        );
+    }
+
+    let metrics = Arc::new(Metrics::new());
+    if let Some(addr) = &args.metrics_addr {
+        serve(addr, metrics.clone()).await?;
+    }
+
+    let filter = BurstFilter {
+        min_bytes: args.min_bytes,
+        max_bytes: args.max_bytes,
+        min_packets: args.min_packets,
+        max_packets: args.max_packets,
+        min_duration: args.min_duration,
+        max_duration: args.max_duration,
+        min_rate: args.min_rate,
+        expression: args.expression.clone(),
+    };
+
+    let publisher = match &args.publish {
+        Some(addr) => Some(
+            Publisher::bind(addr, args.publish_buffer)
+                .await
+                .map_err(|err| format!("failed to publish on {}: {}", addr, err))?,
+        ),
+        None => None,
+    };
+
+    let sink = match &args.sink {
+        Some(url) => {
+            let sink = HttpSink::new(url, args.sink_batch_size, args.sink_interval)?;
+            Some(match &args.sink_spool {
+                Some(dir) => sink.spool(dir)?,
+                None => sink,
+            })
+        }
+        None => None,
+    };
+
+    let database = match &args.sqlite {
+        Some(path) => {
+            let command_line: Vec<String> = std::env::args().collect();
+            Some(SqliteWriter::open(
+                path,
+                &command_line,
+                &config::render(&Args::command(), &matches),
+                &tshark_args(args.clone()),
+            )?)
+        }
+        None => None,
+    };
+
+    let mut writers = Vec::new();
+    let mut columnar_writers = Vec::new();
+    for spec in &args.outputs {
+        if spec.format.is_columnar() {
+            columnar_writers.push(ColumnarWriter::create(
+                spec.filter.clone(),
+                spec.format,
+                &spec.path,
+                args.row_group_size,
+            )?);
+            continue;
+        }
+
+        let writer = OutputWriter::new(spec.filter.clone()).format(spec.format);
+        writers.push(match spec.is_stdout() {
+            true => writer,
+            false => writer.file(&spec.path)?,
+        });
+    }
+
+    let primary = match &args.command {
+        None if stdout_output => None,
+        None if args.tui => Some(Dashboard::new(filter).start().await),
+        None => Some(
+            OutputWriter::new(filter)
+                .metrics(metrics.clone())
+                .start()
+                .await,
+        ),
+        Some(Command::Evaluate {
+            ground_truth,
+            time_tolerance,
+        }) => {
+            let segments = read_segments(ground_truth)
+                .map_err(|err| format!("failed to read ground truth: {}", err))?;
+            Some(
+                Evaluator::new(segments, *time_tolerance, filter)
+                    .start()
+                    .await,
+            )
+        }
+        Some(Command::Fingerprint {
+            database,
+            tolerance,
+            overhead,
+            min_matches,
+        }) => {
+            let database = read_database(database)
+                .map_err(|err| format!("failed to read database: {}", err))?;
+            let options = MatchOptions {
+                tolerance: *tolerance,
+                overhead: *overhead,
+                min_matches: *min_matches,
+            };
+            Some(Fingerprinter::new(database, options, filter).start().await)
+        }
+        Some(Command::BuildDatabase { .. }) => {
+            return Err(
+                "build-database does not read packets and cannot be run with a capture".into(),
+            );
+        }
+        Some(Command::Session {
+            segment_duration,
+            bitrate_ladder,
+        }) => {
+            let bitrate_ladder = bitrate_ladder.as_ref().map(|ladder| {
+                let mut ladder: Vec<f64> = ladder.iter().map(|kbps| kbps * 1000.0).collect();
+                ladder.sort_by(f64::total_cmp);
+                ladder
+            });
+            Some(
+                OutputWriter::new(filter)
+                    .sessions(SessionOptions {
+                        segment_duration: *segment_duration,
+                        bitrate_ladder,
+                    })
+                    .metrics(metrics.clone())
+                    .start()
+                    .await,
+            )
+        }
+    };
+
+    let mut outputs: Vec<_> = primary.into_iter().collect();
+    for mut writer in writers {
+        outputs.push(writer.start().await);
+    }
+    for mut writer in columnar_writers {
+        outputs.push(writer.start().await);
+    }
+    if let Some(mut publisher) = publisher {
+        outputs.push(publisher.start().await);
+    }
+    if let Some(mut sink) = sink {
+        outputs.push(sink.start().await);
+    }
+    if let Some(mut database) = database {
+        outputs.push(database.start().await);
+    }
+    let output = match outputs.len() {
+        1 => outputs.pop().unwrap(),
+        _ => tee(outputs),
+    };
+    // Bursts are output as their flows time out, not in the order they ended. Bursts of
+    // several interfaces are merged into a stream ordered by time, holding them for twice
+    // the burst timeout to allow for capture delays.
+    let (output_tx, output_handle) = match args.interfaces.len() {
+        0 | 1 => output,
+        _ => reorder(output, 2.0 * args.burst_timeout),
+    };
+
+    metrics.watch_output(&output_tx);
 
-    let output_tx = OutputWriter::new(
-        args.min_bytes,
-        args.max_bytes,
-        args.min_packets,
-        args.max_packets,
-    )
-    .start()
-    .await;
+    let annotations = args.annotate.then(|| Arc::new(Annotations::new()));
 
     let opts = CommonOptions {
         tshark_args: tshark_args(args.clone()),
+        source,
         burst_timeout: args.burst_timeout,
+        tag_interfaces: args.interfaces.len() > 1,
         output_tx,
+        metrics,
+        annotations: annotations.clone(),
     };
 
     match args.wlan {
@@ -207,11 +883,24 @@ async fn main() -> Result<(), Box<dyn Error>> {
         true => CaptureType::Wlan {
             opts,
             no_estimation: args.no_estimation,
+            estimator: args.estimator,
             max_deviation: args.max_deviation,
+            gcmp: args.gcmp,
+            bssids: args.bssids.iter().map(|b| b.to_lowercase()).collect(),
         },
     }
     .run()
     .await?;
 
+    // Wait for the remaining bursts to be output once all flows have ended.
+    output_handle.await?;
+
+    if let (Some(annotations), Some(path)) = (annotations, &args.pcap_outfile) {
+        let annotated = annotations
+            .write(path)
+            .map_err(|err| format!("failed to annotate {}: {}", path, err))?;
+        eprintln!("Annotated {} packets in {}", annotated, path);
+    }
+
     Ok(())
 }
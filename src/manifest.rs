@@ -0,0 +1,663 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use roxmltree::{Document, Node};
+
+use crate::fingerprint::Representation;
+
+/// Read the segment sizes of every representation in a DASH MPD (`.mpd`) or HLS playlist
+/// (`.m3u8`, `.m3u`).
+///
+/// Everything is read from disk. Media files and segments referenced by the manifest are
+/// looked up relative to its directory, and absolute URLs by their last path component.
+/// The title defaults to the file stem of the manifest.
+pub fn read_manifest(
+    path: &str,
+    title: Option<&str>,
+) -> Result<Vec<Representation>, Box<dyn Error>> {
+    let path = Path::new(path);
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let title = match title {
+        Some(title) => title.to_string(),
+        None => file_stem(path),
+    };
+
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "mpd" => read_mpd(&fs::read_to_string(path)?, &dir, &title),
+        "m3u8" | "m3u" => read_hls(path, &dir, &title),
+        _ => Err(format!("unknown manifest type: {}", path.display()).into()),
+    }
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Local path of a URL or relative reference.
+fn resolve(dir: &Path, base: &str, reference: &str) -> PathBuf {
+    let reference = format!("{}{}", base, reference);
+    let reference = reference.split(['?', '#']).next().unwrap_or_default();
+
+    match reference.contains("://") {
+        true => dir.join(reference.rsplit('/').next().unwrap_or_default()),
+        false => dir.join(reference),
+    }
+}
+
+fn file_size(path: &Path) -> Result<u64, Box<dyn Error>> {
+    Ok(fs::metadata(path)
+        .map_err(|err| format!("{}: {}", path.display(), err))?
+        .len())
+}
+
+/// Parse an inclusive HTTP byte range "first-last".
+fn parse_range(range: &str) -> Result<(u64, u64), Box<dyn Error>> {
+    let invalid = || format!("invalid byte range: {}", range);
+    let (first, last) = range.split_once('-').ok_or_else(invalid)?;
+    let (first, last): (u64, u64) = (first.trim().parse()?, last.trim().parse()?);
+    if last < first {
+        return Err(invalid().into());
+    }
+    Ok((first, last))
+}
+
+fn read_mpd(xml: &str, dir: &Path, title: &str) -> Result<Vec<Representation>, Box<dyn Error>> {
+    let doc = Document::parse(xml)?;
+    let mpd = doc.root_element();
+    let mpd_duration = mpd.attribute("mediaPresentationDuration");
+
+    let mut representations = Vec::<Representation>::new();
+
+    for period in children(mpd, "Period") {
+        let period_duration = period.attribute("duration").or(mpd_duration);
+
+        for adaptation_set in children(period, "AdaptationSet") {
+            for rep in children(adaptation_set, "Representation") {
+                let id = rep.attribute("id").unwrap_or_default();
+                let bandwidth = rep.attribute("bandwidth").map(str::parse).transpose()?;
+
+                // Elements are inherited from the enclosing levels.
+                let levels = [rep, adaptation_set, period, mpd];
+                let base_url = levels
+                    .iter()
+                    .rev()
+                    .filter_map(|node| child(*node, "BaseURL").and_then(|n| n.text()))
+                    .map(str::trim)
+                    .fold(String::new(), |base, url| match url.contains("://") {
+                        true => url.to_string(),
+                        false => base + url,
+                    });
+                let find = |name: &'static str| levels.iter().find_map(|node| child(*node, name));
+
+                let segments = if let Some(template) = find("SegmentTemplate") {
+                    let duration = period_duration.map(parse_duration).transpose()?;
+                    template_sizes(template, dir, &base_url, id, bandwidth, duration)?
+                } else if let Some(list) = find("SegmentList") {
+                    list_sizes(list, dir, &base_url)?
+                } else if let Some(base) = find("SegmentBase") {
+                    sidx_sizes(base, &resolve(dir, &base_url, ""))?
+                } else {
+                    return Err(format!("no segment information for representation {}", id).into());
+                };
+
+                // Periods continue the representations of earlier periods.
+                match representations.iter_mut().find(|r| r.representation == id) {
+                    Some(existing) => existing.segments.extend(segments),
+                    None => representations.push(Representation {
+                        title: title.to_string(),
+                        representation: id.to_string(),
+                        bandwidth,
+                        segments,
+                    }),
+                }
+            }
+        }
+    }
+
+    Ok(representations)
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |n| n.has_tag_name(name))
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &'static str) -> Option<Node<'a, 'input>> {
+    children(node, name).next()
+}
+
+/// Parse an ISO 8601 duration such as "PT1H2M3.5S" into seconds.
+fn parse_duration(duration: &str) -> Result<f64, Box<dyn Error>> {
+    let invalid = || format!("invalid duration: {}", duration);
+    let mut seconds = 0.0;
+    let mut number = String::new();
+    let mut in_time = false;
+
+    for c in duration.strip_prefix('P').ok_or_else(invalid)?.chars() {
+        let unit = match c {
+            'T' => {
+                in_time = true;
+                continue;
+            }
+            '0'..='9' | '.' => {
+                number.push(c);
+                continue;
+            }
+            'D' if !in_time => 86400.0,
+            'H' if in_time => 3600.0,
+            'M' if in_time => 60.0,
+            'S' if in_time => 1.0,
+            _ => return Err(invalid().into()),
+        };
+        seconds += number.parse::<f64>().map_err(|_| invalid())? * unit;
+        number.clear();
+    }
+
+    Ok(seconds)
+}
+
+fn list_sizes(list: Node, dir: &Path, base_url: &str) -> Result<Vec<u64>, Box<dyn Error>> {
+    children(list, "SegmentURL")
+        .map(|segment| match segment.attribute("mediaRange") {
+            Some(range) => parse_range(range).map(|(first, last)| last - first + 1),
+            None => file_size(&resolve(
+                dir,
+                base_url,
+                segment.attribute("media").unwrap_or_default(),
+            )),
+        })
+        .collect()
+}
+
+fn template_sizes(
+    template: Node,
+    dir: &Path,
+    base_url: &str,
+    id: &str,
+    bandwidth: Option<u64>,
+    period_duration: Option<f64>,
+) -> Result<Vec<u64>, Box<dyn Error>> {
+    let media = template
+        .attribute("media")
+        .ok_or("segment template without media")?;
+    let start_number: u64 = template.attribute("startNumber").unwrap_or("1").parse()?;
+    let timescale: u64 = template.attribute("timescale").unwrap_or("1").parse()?;
+
+    // Number and start time of each segment, None if unknown.
+    let segments: Vec<(u64, Option<u64>)> = match child(template, "SegmentTimeline") {
+        Some(timeline) => {
+            let mut segments = Vec::new();
+            let mut time = 0;
+            for s in children(timeline, "S") {
+                if let Some(t) = s.attribute("t") {
+                    time = t.parse()?;
+                }
+                let d: u64 = s
+                    .attribute("d")
+                    .ok_or("timeline segment without duration")?
+                    .parse()?;
+                let r: i64 = s.attribute("r").unwrap_or("0").parse()?;
+                if r < 0 {
+                    return Err("open-ended segment timeline repeat is not supported".into());
+                }
+                for _ in 0..=r {
+                    segments.push((start_number + segments.len() as u64, Some(time)));
+                    time += d;
+                }
+            }
+            segments
+        }
+        None => {
+            let duration: u64 = template
+                .attribute("duration")
+                .ok_or("segment template without duration or timeline")?
+                .parse()?;
+            match period_duration {
+                Some(period_duration) => {
+                    let count =
+                        (period_duration * timescale as f64 / duration as f64).ceil() as u64;
+                    (0..count).map(|i| (start_number + i, None)).collect()
+                }
+                // Unknown number of segments, read until the next one is missing.
+                None => {
+                    if !media.contains("$Number") {
+                        return Err(format!(
+                            "segment template {} without $Number$ requires a period duration",
+                            media
+                        )
+                        .into());
+                    }
+                    let mut segments = Vec::new();
+                    loop {
+                        let number = start_number + segments.len() as u64;
+                        let path = resolve(
+                            dir,
+                            base_url,
+                            &expand_template(media, id, bandwidth, number, None)?,
+                        );
+                        if !path.exists() {
+                            break segments;
+                        }
+                        segments.push((number, None));
+                    }
+                }
+            }
+        }
+    };
+
+    segments
+        .into_iter()
+        .map(|(number, time)| {
+            file_size(&resolve(
+                dir,
+                base_url,
+                &expand_template(media, id, bandwidth, number, time)?,
+            ))
+        })
+        .collect()
+}
+
+/// Substitute the identifiers of a segment template, including width formatting such as
+/// $Number%05d$.
+fn expand_template(
+    template: &str,
+    id: &str,
+    bandwidth: Option<u64>,
+    number: u64,
+    time: Option<u64>,
+) -> Result<String, Box<dyn Error>> {
+    let mut expanded = String::new();
+    let mut parts = template.split('$');
+
+    expanded.push_str(parts.next().unwrap_or_default());
+
+    // Identifiers are every other part.
+    while let Some(identifier) = parts.next() {
+        let (name, format) = identifier.split_once('%').unwrap_or((identifier, ""));
+        let width: usize = format
+            .trim_start_matches('0')
+            .trim_end_matches('d')
+            .parse()
+            .unwrap_or(0);
+
+        let value = match name {
+            "" => "$".to_string(),
+            "RepresentationID" => id.to_string(),
+            "Number" => number.to_string(),
+            "Bandwidth" => bandwidth.ok_or("template requires bandwidth")?.to_string(),
+            "Time" => time
+                .ok_or("template requires segment timeline")?
+                .to_string(),
+            _ => return Err(format!("unknown template identifier: {}", name).into()),
+        };
+        expanded.push_str(&format!("{:0>width$}", value, width = width));
+        expanded.push_str(parts.next().unwrap_or_default());
+    }
+
+    Ok(expanded)
+}
+
+/// Read the subsegment sizes from the sidx box in the index range of a media file.
+fn sidx_sizes(base: Node, media: &Path) -> Result<Vec<u64>, Box<dyn Error>> {
+    let (first, last) = parse_range(
+        base.attribute("indexRange")
+            .ok_or("segment base without index range")?,
+    )?;
+
+    let mut file = File::open(media).map_err(|err| format!("{}: {}", media.display(), err))?;
+    if last >= file.metadata()?.len() {
+        return Err(format!("index range beyond the end of {}", media.display()).into());
+    }
+    file.seek(SeekFrom::Start(first))?;
+    let mut index = vec![0; (last - first + 1) as usize];
+    file.read_exact(&mut index)?;
+
+    let mut boxes = index.as_slice();
+    let sidx = loop {
+        if boxes.len() < 8 {
+            return Err(format!("no sidx box in index range of {}", media.display()).into());
+        }
+        let size = u32::from_be_bytes(boxes[0..4].try_into()?) as usize;
+        if size < 8 || size > boxes.len() {
+            return Err(format!("invalid box in index range of {}", media.display()).into());
+        }
+        if &boxes[4..8] == b"sidx" {
+            break &boxes[8..size];
+        }
+        boxes = &boxes[size..];
+    };
+
+    let mut reader = Reader(sidx);
+    let version = reader.take(4)?[0];
+    // reference_ID, timescale, earliest_presentation_time, first_offset.
+    reader.take(if version == 0 { 16 } else { 24 })?;
+    // Reserved.
+    reader.take(2)?;
+    let reference_count = u16::from_be_bytes(reader.take(2)?.try_into()?);
+
+    (0..reference_count)
+        .map(|_| {
+            let reference = u32::from_be_bytes(reader.take(4)?.try_into()?);
+            // subsegment_duration, SAP fields.
+            reader.take(8)?;
+            if reference >> 31 == 1 {
+                return Err("hierarchical sidx is not supported".into());
+            }
+            Ok((reference & 0x7fff_ffff) as u64)
+        })
+        .collect()
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if self.0.len() < n {
+            return Err("truncated sidx box".into());
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+}
+
+/// Read a media playlist, or every media playlist of a master playlist.
+fn read_hls(path: &Path, dir: &Path, title: &str) -> Result<Vec<Representation>, Box<dyn Error>> {
+    let playlist =
+        fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    let mut representations = Vec::new();
+    let mut segments = Vec::new();
+    let mut variant_bandwidth = None;
+    let mut byterange = None;
+
+    for line in playlist.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            variant_bandwidth = Some(
+                attributes
+                    .split(',')
+                    .find_map(|a| a.strip_prefix("BANDWIDTH="))
+                    .map(str::parse)
+                    .transpose()?,
+            );
+        } else if let Some(range) = line.strip_prefix("#EXT-X-BYTERANGE:") {
+            let length = range.split('@').next().unwrap_or_default();
+            byterange = Some(length.trim().parse::<u64>()?);
+        } else if line.starts_with('#') {
+            continue;
+        } else if let Some(bandwidth) = variant_bandwidth.take() {
+            let variant = resolve(dir, "", line);
+            let variant_dir = variant.parent().unwrap_or(Path::new("")).to_path_buf();
+            for mut representation in read_hls(&variant, &variant_dir, title)? {
+                representation.bandwidth = representation.bandwidth.or(bandwidth);
+                representations.push(representation);
+            }
+        } else {
+            segments.push(match byterange.take() {
+                Some(length) => length,
+                None => file_size(&resolve(dir, "", line))?,
+            });
+        }
+    }
+
+    if !segments.is_empty() {
+        representations.push(Representation {
+            title: title.to_string(),
+            representation: file_stem(path),
+            bandwidth: None,
+            segments,
+        });
+    }
+
+    Ok(representations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A temporary directory with the given files, each a path and its contents.
+    fn directory(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("burstshark-test-{}-{}", std::process::id(), name));
+        fs::remove_dir_all(&dir).ok();
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn read(dir: &Path, manifest: &str) -> Result<Vec<Representation>, String> {
+        read_manifest(dir.join(manifest).to_str().unwrap(), Some("Title"))
+            .map_err(|e| e.to_string())
+    }
+
+    fn segments(representations: &[Representation]) -> Vec<(&str, Option<u64>, &[u64])> {
+        representations
+            .iter()
+            .map(|r| {
+                (
+                    r.representation.as_str(),
+                    r.bandwidth,
+                    r.segments.as_slice(),
+                )
+            })
+            .collect()
+    }
+
+    fn bytes(n: usize) -> String {
+        "x".repeat(n)
+    }
+
+    #[test]
+    fn segment_template_with_number() {
+        let mpd = r#"<MPD mediaPresentationDuration="PT5S">
+            <Period>
+              <AdaptationSet>
+                <SegmentTemplate media="$RepresentationID$/seg-$Number%03d$.m4s" startNumber="1" duration="4" timescale="2"/>
+                <Representation id="720p" bandwidth="3000000"/>
+                <Representation id="1080p" bandwidth="6000000"/>
+              </AdaptationSet>
+            </Period>
+          </MPD>"#;
+        let dir = directory(
+            "number",
+            &[
+                ("video.mpd", mpd),
+                ("720p/seg-001.m4s", &bytes(100)),
+                ("720p/seg-002.m4s", &bytes(200)),
+                ("720p/seg-003.m4s", &bytes(300)),
+                ("1080p/seg-001.m4s", &bytes(400)),
+                ("1080p/seg-002.m4s", &bytes(500)),
+                ("1080p/seg-003.m4s", &bytes(600)),
+            ],
+        );
+
+        // 5 s of 2 s segments.
+        let representations = read(&dir, "video.mpd").unwrap();
+        assert_eq!(
+            segments(&representations),
+            vec![
+                ("720p", Some(3000000), &[100, 200, 300][..]),
+                ("1080p", Some(6000000), &[400, 500, 600][..]),
+            ]
+        );
+        assert!(representations.iter().all(|r| r.title == "Title"));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn segment_template_with_number_until_missing() {
+        let mpd = r#"<MPD>
+            <Period>
+              <AdaptationSet>
+                <Representation id="audio">
+                  <SegmentTemplate media="a$Number$.m4s" startNumber="0" duration="2"/>
+                </Representation>
+              </AdaptationSet>
+            </Period>
+          </MPD>"#;
+        let dir = directory(
+            "until-missing",
+            &[
+                ("audio.mpd", mpd),
+                ("a0.m4s", &bytes(10)),
+                ("a1.m4s", &bytes(20)),
+            ],
+        );
+
+        let representations = read(&dir, "audio.mpd").unwrap();
+        assert_eq!(
+            segments(&representations),
+            vec![("audio", None, &[10, 20][..])]
+        );
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn segment_template_without_number_or_duration() {
+        let mpd = r#"<MPD>
+            <Period>
+              <AdaptationSet>
+                <SegmentTemplate media="$RepresentationID$.m4s" duration="2"/>
+                <Representation id="video"/>
+              </AdaptationSet>
+            </Period>
+          </MPD>"#;
+        let dir = directory(
+            "no-number",
+            &[("video.mpd", mpd), ("video.m4s", &bytes(10))],
+        );
+
+        assert_eq!(
+            read(&dir, "video.mpd").unwrap_err(),
+            "segment template $RepresentationID$.m4s without $Number$ requires a period duration"
+        );
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn segment_template_with_time() {
+        let mpd = r#"<MPD mediaPresentationDuration="PT5.5S">
+            <Period>
+              <BaseURL>media/</BaseURL>
+              <AdaptationSet>
+                <SegmentTemplate media="$RepresentationID$-$Time$.m4s" timescale="1000">
+                  <SegmentTimeline>
+                    <S t="1000" d="2000" r="1"/>
+                    <S d="1500"/>
+                  </SegmentTimeline>
+                </SegmentTemplate>
+                <Representation id="v1" bandwidth="1000000"/>
+              </AdaptationSet>
+            </Period>
+          </MPD>"#;
+        let dir = directory(
+            "time",
+            &[
+                ("video.mpd", mpd),
+                ("media/v1-1000.m4s", &bytes(100)),
+                ("media/v1-3000.m4s", &bytes(200)),
+                ("media/v1-5000.m4s", &bytes(150)),
+            ],
+        );
+
+        let representations = read(&dir, "video.mpd").unwrap();
+        assert_eq!(
+            segments(&representations),
+            vec![("v1", Some(1000000), &[100, 200, 150][..])]
+        );
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn segment_template_with_time_requires_timeline() {
+        let mpd = r#"<MPD mediaPresentationDuration="PT4S">
+            <Period>
+              <AdaptationSet>
+                <SegmentTemplate media="$Time$.m4s" duration="2"/>
+                <Representation id="v1"/>
+              </AdaptationSet>
+            </Period>
+          </MPD>"#;
+        let dir = directory("time-without-timeline", &[("video.mpd", mpd)]);
+
+        assert_eq!(
+            read(&dir, "video.mpd").unwrap_err(),
+            "template requires segment timeline"
+        );
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn hls_ladder() {
+        let master = "#EXTM3U\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360\n\
+            low/index.m3u8\n\
+            #EXT-X-STREAM-INF:RESOLUTION=1920x1080,BANDWIDTH=2400000\n\
+            high.m3u8\n";
+        let low = "#EXTM3U\n#EXTINF:4.0,\na.ts\n#EXTINF:4.0,\nb.ts\n#EXT-X-ENDLIST\n";
+        let high = "#EXTM3U\n\
+            #EXTINF:4.0,\n#EXT-X-BYTERANGE:1000@0\nhigh.ts\n\
+            #EXTINF:4.0,\n#EXT-X-BYTERANGE:1200\nhigh.ts\n\
+            #EXT-X-ENDLIST\n";
+        let dir = directory(
+            "hls",
+            &[
+                ("master.m3u8", master),
+                ("low/index.m3u8", low),
+                ("low/a.ts", &bytes(300)),
+                ("low/b.ts", &bytes(400)),
+                ("high.m3u8", high),
+            ],
+        );
+
+        let representations = read(&dir, "master.m3u8").unwrap();
+        assert_eq!(
+            segments(&representations),
+            vec![
+                ("index", Some(800000), &[300, 400][..]),
+                ("high", Some(2400000), &[1000, 1200][..]),
+            ]
+        );
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(parse_range("0-99").unwrap(), (0, 99));
+        assert_eq!(parse_range("100 - 100").unwrap(), (100, 100));
+        assert_eq!(
+            parse_range("100-99").unwrap_err().to_string(),
+            "invalid byte range: 100-99"
+        );
+        assert!(parse_range("100").is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("PT1H2M3.5S").unwrap(), 3723.5);
+        assert_eq!(parse_duration("P1DT1S").unwrap(), 86401.0);
+        assert!(parse_duration("1S").is_err());
+        assert!(parse_duration("PT1D").is_err());
+    }
+}
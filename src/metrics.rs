@@ -0,0 +1,350 @@
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+use crate::capture::Burst;
+
+/// Upper bounds in bytes of the burst size histogram buckets.
+const SIZE_BUCKETS: &[f64] = &[1e3, 4e3, 16e3, 64e3, 256e3, 1e6, 4e6, 16e6, 64e6];
+
+/// Upper bounds in seconds of the burst duration histogram buckets.
+const DURATION_BUCKETS: &[f64] = &[0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Time to wait before accepting connections again after accepting one failed, e.g.
+/// because the process has run out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Maximum length of a request to the metrics endpoint.
+const MAX_REQUEST_LEN: u64 = 8192;
+
+/// Counters and histograms of a capture, exposed in the Prometheus text format.
+#[derive(Debug)]
+pub struct Metrics {
+    /// Packets parsed by the ID of the probe that captured them, if any.
+    packets: Mutex<Vec<(Option<String>, Arc<AtomicU64>)>>,
+    parse_errors: AtomicU64,
+    active_flows: AtomicU64,
+    bursts: AtomicU64,
+    filtered_bursts: AtomicU64,
+    burst_size: Histogram,
+    burst_duration: Histogram,
+    /// Bursts that waited for the output to consume earlier bursts.
+    blocked_bursts: AtomicU64,
+    /// Nanoseconds flows spent waiting for the output.
+    blocked_nanos: AtomicU64,
+    output: Mutex<Option<mpsc::WeakSender<Burst>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            packets: Mutex::new(Vec::new()),
+            parse_errors: AtomicU64::new(0),
+            active_flows: AtomicU64::new(0),
+            bursts: AtomicU64::new(0),
+            filtered_bursts: AtomicU64::new(0),
+            burst_size: Histogram::new(SIZE_BUCKETS),
+            burst_duration: Histogram::new(DURATION_BUCKETS),
+            blocked_bursts: AtomicU64::new(0),
+            blocked_nanos: AtomicU64::new(0),
+            output: Mutex::new(None),
+        }
+    }
+
+    /// Counter of the packets parsed from a probe, or from the local capture if None.
+    pub fn packet_counter(&self, probe: Option<&str>) -> Arc<AtomicU64> {
+        let mut packets = self.packets.lock().unwrap();
+        if let Some((_, counter)) = packets.iter().find(|(p, _)| p.as_deref() == probe) {
+            return counter.clone();
+        }
+        let counter = Arc::new(AtomicU64::new(0));
+        packets.push((probe.map(str::to_string), counter.clone()));
+        counter
+    }
+
+    pub fn add_parse_error(&self) {
+        self.parse_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_flow(&self) {
+        self.active_flows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn remove_flow(&self) {
+        self.active_flows.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn add_burst(&self, burst: &Burst) {
+        self.bursts.fetch_add(1, Ordering::Relaxed);
+        self.burst_size.observe(burst.size as f64);
+        self.burst_duration.observe(burst.duration());
+    }
+
+    pub fn add_filtered_burst(&self) {
+        self.filtered_bursts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_blocked_burst(&self, waited: Duration) {
+        self.blocked_bursts.fetch_add(1, Ordering::Relaxed);
+        self.blocked_nanos
+            .fetch_add(waited.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Report the number of bursts queued for the output. Does not keep the output open.
+    pub fn watch_output(&self, output_tx: &mpsc::Sender<Burst>) {
+        *self.output.lock().unwrap() = Some(output_tx.downgrade());
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "packets_parsed_total",
+            "counter",
+            "Packets parsed from tshark.",
+        );
+        for (probe, counter) in self.packets.lock().unwrap().iter() {
+            let labels = probe
+                .as_ref()
+                .map_or(String::new(), |p| format!("{{probe=\"{}\"}}", escape(p)));
+            let value = counter.load(Ordering::Relaxed);
+            writeln!(out, "burstshark_packets_parsed_total{} {}", labels, value).unwrap();
+        }
+
+        counter(
+            &mut out,
+            "packet_parse_errors_total",
+            "Lines from tshark that could not be parsed and were skipped.",
+            self.parse_errors.load(Ordering::Relaxed),
+        );
+
+        header(
+            &mut out,
+            "active_flows",
+            "gauge",
+            "Flows currently tracked.",
+        );
+        let flows = self.active_flows.load(Ordering::Relaxed);
+        writeln!(out, "burstshark_active_flows {}", flows).unwrap();
+
+        counter(
+            &mut out,
+            "bursts_emitted_total",
+            "Completed bursts sent to the output.",
+            self.bursts.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "bursts_filtered_total",
+            "Bursts not output due to the filter options.",
+            self.filtered_bursts.load(Ordering::Relaxed),
+        );
+
+        self.burst_size
+            .render(&mut out, "burst_size_bytes", "Size of the emitted bursts.");
+        self.burst_duration.render(
+            &mut out,
+            "burst_duration_seconds",
+            "Time between the first and last packet of the emitted bursts.",
+        );
+
+        counter(
+            &mut out,
+            "output_blocked_bursts_total",
+            "Bursts that waited for the output to consume earlier bursts.",
+            self.blocked_bursts.load(Ordering::Relaxed),
+        );
+        header(
+            &mut out,
+            "output_blocked_seconds_total",
+            "counter",
+            "Time flows spent waiting for the output to consume bursts.",
+        );
+        let blocked = self.blocked_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        writeln!(out, "burstshark_output_blocked_seconds_total {}", blocked).unwrap();
+
+        let queued = self
+            .output
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|output| output.upgrade())
+            .map_or(0, |tx| tx.max_capacity() - tx.capacity());
+        header(
+            &mut out,
+            "output_queue_bursts",
+            "gauge",
+            "Bursts queued for the output.",
+        );
+        writeln!(out, "burstshark_output_queue_bursts {}", queued).unwrap();
+
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+struct Histogram {
+    /// Upper bounds of the buckets in ascending order.
+    bounds: &'static [f64],
+    state: Mutex<HistogramState>,
+}
+
+#[derive(Debug)]
+struct HistogramState {
+    /// Observations within each bucket, not cumulative.
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            state: Mutex::new(HistogramState {
+                buckets: vec![0; bounds.len()],
+                count: 0,
+                sum: 0.0,
+            }),
+        }
+    }
+
+    fn observe(&self, value: f64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(bucket) = self.bounds.iter().position(|&bound| value <= bound) {
+            state.buckets[bucket] += 1;
+        }
+        state.count += 1;
+        state.sum += value;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let state = self.state.lock().unwrap();
+        header(out, name, "histogram", help);
+
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&state.buckets) {
+            cumulative += count;
+            writeln!(
+                out,
+                "burstshark_{}_bucket{{le=\"{}\"}} {}",
+                name, bound, cumulative
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "burstshark_{}_bucket{{le=\"+Inf\"}} {}",
+            name, state.count
+        )
+        .unwrap();
+        writeln!(out, "burstshark_{}_sum {}", name, state.sum).unwrap();
+        writeln!(out, "burstshark_{}_count {}", name, state.count).unwrap();
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP burstshark_{} {}", name, help).unwrap();
+    writeln!(out, "# TYPE burstshark_{} {}", name, kind).unwrap();
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "counter", help);
+    writeln!(out, "burstshark_{} {}", name, value).unwrap();
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serve the metrics over HTTP at addr, under /metrics, until the program exits.
+pub async fn serve(addr: &str, metrics: Arc<Metrics>) -> Result<JoinHandle<()>, Box<dyn Error>> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|err| format!("failed to serve metrics on {}: {}", addr, err))?;
+    eprintln!("Serving metrics on http://{}/metrics", addr);
+
+    Ok(tokio::spawn(async move {
+        loop {
+            let socket = match listener.accept().await {
+                Ok((socket, _)) => socket,
+                Err(err) => {
+                    eprintln!("Failed to accept metrics connection: {}", err);
+                    sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            let metrics = metrics.clone();
+
+            tokio::spawn(async move {
+                // Scrapers retry failed requests, errors are not reported.
+                respond(socket, &metrics).await.ok();
+            });
+        }
+    }))
+}
+
+/// Answer a single HTTP request and close the connection.
+async fn respond(socket: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut reader = BufReader::new(socket);
+
+    let mut request = String::new();
+    (&mut reader)
+        .take(MAX_REQUEST_LEN)
+        .read_line(&mut request)
+        .await?;
+
+    // Skip the headers.
+    let mut line = String::new();
+    while (&mut reader)
+        .take(MAX_REQUEST_LEN)
+        .read_line(&mut line)
+        .await?
+        > 0
+    {
+        if line.trim_end().is_empty() {
+            break;
+        }
+        line.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        (Some("GET"), _) => ("404 Not Found", "Not found, see /metrics\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "Only GET is supported\n".to_string(),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    );
+
+    let mut socket = reader.into_inner();
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
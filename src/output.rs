@@ -1,84 +1,437 @@
-use std::io::{stdout, Write};
+use std::error::Error;
+use std::fs::File;
+use std::io::{stdout, BufWriter, Write};
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::SystemTime;
 
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
 
 use crate::capture::Burst;
+use crate::expression::{parse_number, Expression};
+use crate::metrics::Metrics;
+use crate::session::{SegmentReport, SessionAnalyzer, SessionOptions, Switch};
+
+/// Bounds a burst must be within, and an expression it must match, to be output.
+#[derive(Debug, Clone, Default)]
+pub struct BurstFilter {
+    pub min_bytes: Option<u32>,
+    pub max_bytes: Option<u32>,
+    pub min_packets: Option<u16>,
+    pub max_packets: Option<u16>,
+    pub min_duration: Option<f64>,
+    pub max_duration: Option<f64>,
+    /// Bursts without a rate, as all their packets have the same timestamp, are below it.
+    pub min_rate: Option<f64>,
+    pub expression: Option<Expression>,
+}
+
+impl BurstFilter {
+    pub fn matches(&self, burst: &Burst) -> bool {
+        !(self.min_bytes.is_some_and(|min| burst.size < min)
+            || self.max_bytes.is_some_and(|max| burst.size > max)
+            || self.min_packets.is_some_and(|min| burst.num_packets < min)
+            || self.max_packets.is_some_and(|max| burst.num_packets > max)
+            || self.min_duration.is_some_and(|min| burst.duration() < min)
+            || self.max_duration.is_some_and(|max| burst.duration() > max)
+            || self
+                .min_rate
+                .is_some_and(|min| burst.rate().is_none_or(|rate| rate < min))
+            || self.expression.as_ref().is_some_and(|e| !e.matches(burst)))
+    }
+}
+
+/// How bursts are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Aligned columns, as printed by default.
+    Text,
+    /// A JSON object per line.
+    Jsonl,
+    /// A Parquet file, written by ColumnarWriter.
+    Parquet,
+    /// An Arrow IPC file, written by ColumnarWriter.
+    Arrow,
+}
+
+impl Format {
+    pub fn is_columnar(self) -> bool {
+        matches!(self, Format::Parquet | Format::Arrow)
+    }
+}
+
+/// An additional output, given as FORMAT:PATH followed by optional comma-separated
+/// filters, e.g. jsonl:bursts.jsonl,min-bytes=100000 or text:-,filter=dst_port==443. A PATH
+/// of - is standard output.
+#[derive(Debug, Clone)]
+pub struct OutputSpec {
+    pub format: Format,
+    pub path: String,
+    pub filter: BurstFilter,
+}
+
+impl OutputSpec {
+    pub fn is_stdout(&self) -> bool {
+        self.path == "-"
+    }
+}
+
+impl FromStr for OutputSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let Some((format, rest)) = spec.split_once(':') else {
+            return Err("expected FORMAT:PATH, e.g. jsonl:bursts.jsonl".to_string());
+        };
+        let format = match format {
+            "text" => Format::Text,
+            "jsonl" => Format::Jsonl,
+            "parquet" => Format::Parquet,
+            "arrow" => Format::Arrow,
+            _ => {
+                return Err(format!(
+                    "unknown format {}, expected text, jsonl, parquet or arrow",
+                    format
+                ))
+            }
+        };
+
+        let mut parts = rest.split(',');
+        let path = parts.next().unwrap_or_default().to_string();
+        if path.is_empty() {
+            return Err("no path, use - for standard output".to_string());
+        }
+        if path == "-" && format.is_columnar() {
+            return Err("parquet and arrow outputs must be written to a file".to_string());
+        }
+
+        let mut filter = BurstFilter::default();
+        for part in parts {
+            let Some((key, value)) = part.split_once('=') else {
+                return Err(format!("expected KEY=VALUE, got {}", part));
+            };
+            match key {
+                "min-bytes" => filter.min_bytes = Some(parse_value(key, value)?),
+                "max-bytes" => filter.max_bytes = Some(parse_value(key, value)?),
+                "min-packets" => filter.min_packets = Some(parse_value(key, value)?),
+                "max-packets" => filter.max_packets = Some(parse_value(key, value)?),
+                "min-duration" => filter.min_duration = Some(parse_value(key, value)?),
+                "max-duration" => filter.max_duration = Some(parse_value(key, value)?),
+                "min-rate" => filter.min_rate = Some(parse_number(value)?),
+                "filter" => filter.expression = Some(value.parse()?),
+                _ => return Err(format!("unknown filter {}", key)),
+            }
+        }
+
+        Ok(OutputSpec {
+            format,
+            path,
+            filter,
+        })
+    }
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|err| format!("invalid {}: {}", key, err))
+}
+
+/// Forward bursts to several outputs. Completes when all outputs have completed.
+pub fn tee(
+    outputs: Vec<(mpsc::Sender<Burst>, JoinHandle<()>)>,
+) -> (mpsc::Sender<Burst>, JoinHandle<()>) {
+    let (tx, mut rx) = mpsc::channel::<Burst>(100);
+
+    let handle = tokio::spawn(async move {
+        let (senders, handles): (Vec<_>, Vec<_>) = outputs.into_iter().unzip();
+
+        while let Some(burst) = rx.recv().await {
+            for sender in &senders {
+                // An output may stop early, e.g. when quitting the dashboard.
+                sender.send(burst.clone()).await.ok();
+            }
+        }
+
+        drop(senders);
+        for handle in handles {
+            handle.await.ok();
+        }
+    });
+
+    (tx, handle)
+}
+
+/// Forward bursts to an output in the order they ended, rather than the order they
+/// completed. A burst is held until one ending delay seconds later has been received, or
+/// no burst has been received for delay seconds.
+pub fn reorder(
+    output: (mpsc::Sender<Burst>, JoinHandle<()>),
+    delay: f64,
+) -> (mpsc::Sender<Burst>, JoinHandle<()>) {
+    let (tx, mut rx) = mpsc::channel::<Burst>(100);
+    let (output_tx, output_handle) = output;
+
+    let handle = tokio::spawn(async move {
+        // Bursts held back, ordered by end.
+        let mut held: Vec<Burst> = Vec::new();
+        let mut watermark = f64::NEG_INFINITY;
+
+        loop {
+            let release = tokio::select! {
+                burst = rx.recv() => match burst {
+                    Some(burst) => {
+                        watermark = watermark.max(burst.end - delay);
+                        let pos = held.partition_point(|b| b.end <= burst.end);
+                        held.insert(pos, burst);
+                        held.partition_point(|b| b.end <= watermark)
+                    },
+                    None => break,
+                },
+                _ = sleep(Duration::from_secs_f64(delay)), if !held.is_empty() => held.len(),
+            };
+
+            for burst in held.drain(..release) {
+                if output_tx.send(burst).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        for burst in held {
+            if output_tx.send(burst).await.is_err() {
+                break;
+            }
+        }
+        drop(output_tx);
+        output_handle.await.ok();
+    });
+
+    (tx, handle)
+}
 
 pub struct OutputWriter {
-    min_bytes: Option<u32>,
-    max_bytes: Option<u32>,
-    min_packets: Option<u16>,
-    max_packets: Option<u16>,
+    filter: BurstFilter,
+    format: Format,
+    /// Written to instead of standard output if set.
+    file: Option<BufWriter<File>>,
+    session_options: Option<SessionOptions>,
+    metrics: Option<Arc<Metrics>>,
 }
 
 impl OutputWriter {
-    pub fn new(
-        min_bytes: Option<u32>,
-        max_bytes: Option<u32>,
-        min_packets: Option<u16>,
-        max_packets: Option<u16>,
-    ) -> Self {
+    pub fn new(filter: BurstFilter) -> Self {
         OutputWriter {
-            min_bytes,
-            max_bytes,
-            min_packets,
-            max_packets,
+            filter,
+            format: Format::Text,
+            file: None,
+            session_options: None,
+            metrics: None,
         }
     }
 
-    pub async fn start(&mut self) -> mpsc::Sender<Burst> {
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Write to a file instead of standard output, truncating it if it exists.
+    pub fn file(mut self, path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = File::create(path)
+            .map_err(|err| format!("failed to create output file {}: {}", path, err))?;
+        self.file = Some(BufWriter::new(file));
+        Ok(self)
+    }
+
+    /// Output the segments of playback sessions instead of bursts.
+    pub fn sessions(mut self, options: SessionOptions) -> Self {
+        self.session_options = Some(options);
+        self
+    }
+
+    /// Count the bursts that are not output due to the filter.
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub async fn start(&mut self) -> (mpsc::Sender<Burst>, JoinHandle<()>) {
         let (tx, mut rx) = mpsc::channel::<Burst>(100);
 
-        let min_bytes = self.min_bytes;
-        let max_bytes = self.max_bytes;
-        let min_packets = self.min_packets;
-        let max_packets = self.max_packets;
+        let filter = self.filter.clone();
+        let mut analyzer = self.session_options.clone().map(SessionAnalyzer::new);
+        let metrics = self.metrics.clone();
+        let format = self.format;
+        let mut out: Box<dyn Write + Send> = match self.file.take() {
+            Some(file) => Box::new(file),
+            None => Box::new(stdout()),
+        };
 
-        tokio::spawn(async move {
-            let stdout = stdout();
+        let handle = tokio::spawn(async move {
             let start_time = SystemTime::now();
             let mut count = 0;
 
             while let Some(burst) = rx.recv().await {
-                if (min_bytes.map_or(false, |min| burst.size < min))
-                    || (max_bytes.map_or(false, |max| burst.size > max))
-                    || (min_packets.map_or(false, |min| burst.num_packets < min))
-                    || (max_packets.map_or(false, |max| burst.num_packets > max))
-                {
+                if !filter.matches(&burst) {
+                    if let Some(metrics) = &metrics {
+                        metrics.add_filtered_burst();
+                    }
+                    continue;
+                }
+
+                if let Some(analyzer) = analyzer.as_mut() {
+                    for report in analyzer.add_burst(&burst) {
+                        count += 1;
+                        write_segment(&mut out, count, start_time, &report);
+                        out.flush().unwrap();
+                    }
                     continue;
                 }
 
                 count += 1;
+                match format {
+                    Format::Text => write_burst(&mut out, count, start_time, &burst),
+                    Format::Jsonl => {
+                        serde_json::to_writer(&mut out, &burst).unwrap();
+                        writeln!(out).unwrap();
+                    }
+                    Format::Parquet | Format::Arrow => unreachable!("written by ColumnarWriter"),
+                }
+                out.flush().unwrap();
+            }
 
-                let elapsed = start_time.elapsed().unwrap_or_default().as_secs_f64();
-                let delay = SystemTime::UNIX_EPOCH
-                    .elapsed()
-                    .unwrap_or_default()
-                    .as_secs_f64()
-                    - burst.end;
-
-                let mut handle = stdout.lock();
-
-                writeln!(
-                    &mut handle,
-                    "{:5} {:13.9} {:15} {:6} {:15} {:5} {:13.9} {:13.9} {:13.9} {:4} {}",
-                    count,
-                    elapsed,
-                    burst.src,
-                    burst.src_port,
-                    burst.dst,
-                    burst.dst_port,
-                    burst.start,
-                    burst.end,
-                    delay,
-                    burst.num_packets,
-                    burst.size,
-                )
-                .unwrap();
+            if let Some(analyzer) = analyzer.as_mut() {
+                for report in analyzer.finish() {
+                    count += 1;
+                    write_segment(&mut out, count, start_time, &report);
+                    out.flush().unwrap();
+                }
+                if analyzer.num_unanalyzed() > 0 {
+                    eprintln!(
+                        "Skipped {} sessions with a single burst, use --segment-duration to analyze them",
+                        analyzer.num_unanalyzed()
+                    );
+                }
             }
         });
 
-        tx
+        (tx, handle)
     }
 }
+
+fn or_dash(value: Option<impl ToString>) -> String {
+    value.map_or("-".to_string(), |v| v.to_string())
+}
+
+fn write_burst(out: &mut dyn Write, count: u32, start_time: SystemTime, burst: &Burst) {
+    let elapsed = start_time.elapsed().unwrap_or_default().as_secs_f64();
+    let delay = SystemTime::UNIX_EPOCH
+        .elapsed()
+        .unwrap_or_default()
+        .as_secs_f64()
+        - burst.end;
+
+    write!(
+        out,
+        "{:5} {:13.9} {:15} {:6} {:15} {:5} {:13.9} {:13.9} {:13.9} {:4} {}",
+        count,
+        elapsed,
+        burst.src,
+        burst.src_port,
+        burst.dst,
+        burst.dst_port,
+        burst.start,
+        burst.end,
+        delay,
+        burst.num_packets,
+        burst.size,
+    )
+    .unwrap();
+
+    if let Some(probe) = &burst.probe {
+        write!(out, " {}", probe).unwrap();
+    }
+
+    if let Some(interface) = &burst.interface {
+        write!(out, " {}", interface).unwrap();
+    }
+
+    if let Some(wlan) = &burst.wlan {
+        let radio = &wlan.radio;
+        write!(
+            out,
+            " {:17} {:17} {:17} {:8} {:>6} {:>4} {:>3} {:>7} {:>3} {:5.3} {:4} {:8} {}",
+            wlan.bssid.as_deref().unwrap_or("-"),
+            wlan.transmitter,
+            wlan.receiver,
+            or_dash(wlan.direction),
+            or_dash(radio.mean_signal.map(|s| format!("{:.1}", s))),
+            or_dash(radio.min_signal),
+            or_dash(radio.dominant_mcs),
+            or_dash(radio.mean_data_rate.map(|r| format!("{:.1}", r))),
+            or_dash(radio.channel),
+            radio.retry_fraction,
+            wlan.num_estimated,
+            wlan.estimated_size,
+            wlan.raw_size,
+        )
+        .unwrap();
+    }
+
+    // Derived columns come last.
+    writeln!(
+        out,
+        " {:10.6} {:>12}",
+        burst.duration(),
+        or_dash(burst.rate().map(|r| format!("{:.0}", r))),
+    )
+    .unwrap();
+}
+
+fn write_segment(out: &mut dyn Write, count: u32, start_time: SystemTime, report: &SegmentReport) {
+    let elapsed = start_time.elapsed().unwrap_or_default().as_secs_f64();
+    let switch = match report.switch {
+        Switch::None => "-",
+        Switch::Up => "up",
+        Switch::Down => "down",
+    };
+
+    write!(
+        out,
+        "{:5} {:13.9} {:15} {:6} {:15} {:5} {:4} {:5} {:13.9} {:13.9} {:6.3} {:10} {:10.1} {:3} {:10.1} {:4} {:8.3} {:8.3}",
+        count,
+        elapsed,
+        report.src,
+        report.src_port,
+        report.dst,
+        report.dst_port,
+        report.session,
+        report.index,
+        report.start,
+        report.end,
+        report.duration,
+        report.size,
+        report.bitrate / 1000.0,
+        report.level,
+        report.level_bitrate / 1000.0,
+        switch,
+        report.buffer,
+        report.stall,
+    )
+    .unwrap();
+
+    if let Some(probe) = &report.probe {
+        write!(out, " {}", probe).unwrap();
+    }
+
+    if let Some(interface) = &report.interface {
+        write!(out, " {}", interface).unwrap();
+    }
+
+    writeln!(out).unwrap();
+}
@@ -0,0 +1,271 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+
+/// Maximum number of pcapng blocks read to find the timestamp of the first packet.
+const MAX_PCAPNG_BLOCKS: usize = 1000;
+
+/// Maximum size of a pcap record or pcapng block.
+const MAX_RECORD_LEN: usize = 1 << 26;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Pcap,
+    Pcapng,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    /// pcap file header.
+    FileHeader,
+    /// pcapng section header block.
+    SectionHeader,
+    /// pcap record or pcapng packet block.
+    Packet,
+    /// Any other pcapng block, e.g. an interface description.
+    Other,
+}
+
+/// A pcap record or pcapng block, including its headers.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub kind: RecordKind,
+    pub data: Vec<u8>,
+}
+
+/// Splits a pcap or pcapng byte stream into records as it arrives.
+#[derive(Debug, Default)]
+pub struct RecordReader {
+    format: Option<Format>,
+    little_endian: bool,
+    buf: Vec<u8>,
+    /// Start of the unread bytes in buf.
+    pos: usize,
+}
+
+impl RecordReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Format of the stream, once its header has been read.
+    pub fn format(&self) -> Option<Format> {
+        self.format
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        self.buf.extend_from_slice(data);
+    }
+
+    /// The next complete record, or None if more data is needed.
+    pub fn next_record(&mut self) -> Result<Option<Record>, String> {
+        let data = &self.buf[self.pos..];
+        if data.len() < 12 {
+            return Ok(None);
+        }
+
+        let (kind, len) = match self.format {
+            None => match data[0..4] {
+                [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => {
+                    self.little_endian = true;
+                    self.format = Some(Format::Pcap);
+                    (RecordKind::FileHeader, 24)
+                }
+                [0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => {
+                    self.little_endian = false;
+                    self.format = Some(Format::Pcap);
+                    (RecordKind::FileHeader, 24)
+                }
+                [0x0a, 0x0d, 0x0d, 0x0a] => {
+                    self.format = Some(Format::Pcapng);
+                    return self.next_record();
+                }
+                _ => return Err("not a pcap or pcapng stream".to_string()),
+            },
+            Some(Format::Pcap) => {
+                if data.len() < 16 {
+                    return Ok(None);
+                }
+                let len = to_u32(&data[8..12], self.little_endian) as usize;
+                (RecordKind::Packet, 16 + len)
+            }
+            Some(Format::Pcapng) => {
+                let block_type = to_u32(&data[0..4], self.little_endian);
+                let kind = match block_type {
+                    // Each section has its own byte order.
+                    0x0a0d0d0a => {
+                        self.little_endian = match data[8..12] {
+                            [0x4d, 0x3c, 0x2b, 0x1a] => true,
+                            [0x1a, 0x2b, 0x3c, 0x4d] => false,
+                            _ => return Err("invalid pcapng byte-order magic".to_string()),
+                        };
+                        RecordKind::SectionHeader
+                    }
+                    2 | 3 | 6 => RecordKind::Packet,
+                    _ => RecordKind::Other,
+                };
+                let len = to_u32(&data[4..8], self.little_endian) as usize;
+                if len < 12 || !len.is_multiple_of(4) {
+                    return Err(format!("invalid pcapng block length {}", len));
+                }
+                (kind, len)
+            }
+        };
+
+        if len > MAX_RECORD_LEN {
+            return Err(format!("record length {} too large", len));
+        }
+        if data.len() < len {
+            return Ok(None);
+        }
+
+        let data = data[..len].to_vec();
+        self.pos += len;
+
+        Ok(Some(Record { kind, data }))
+    }
+}
+
+/// Timestamp of the first packet of a pcap or pcapng file, which may be gzipped. None for
+/// other formats or if the file cannot be read.
+pub fn first_timestamp(path: &Path) -> Option<f64> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+
+    if reader.fill_buf().ok()?.starts_with(&[0x1f, 0x8b]) {
+        read_first_timestamp(GzDecoder::new(reader))
+    } else {
+        read_first_timestamp(reader)
+    }
+}
+
+fn read_first_timestamp(mut reader: impl Read) -> Option<f64> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic).ok()?;
+
+    // pcap magic numbers for microsecond and nanosecond resolution in either byte order.
+    let (little_endian, resolution) = match magic {
+        [0xd4, 0xc3, 0xb2, 0xa1] => (true, 1e-6),
+        [0xa1, 0xb2, 0xc3, 0xd4] => (false, 1e-6),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (true, 1e-9),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (false, 1e-9),
+        [0x0a, 0x0d, 0x0d, 0x0a] => return read_pcapng_first_timestamp(reader),
+        _ => return None,
+    };
+
+    // Skip the rest of the file header to the first record header.
+    let mut header = [0; 28];
+    reader.read_exact(&mut header).ok()?;
+    let read_u32 = |bytes: &[u8]| to_u32(bytes, little_endian);
+
+    Some(read_u32(&header[20..24]) as f64 + read_u32(&header[24..28]) as f64 * resolution)
+}
+
+/// Read pcapng blocks, following the section header block type, up to the first packet.
+fn read_pcapng_first_timestamp(mut reader: impl Read) -> Option<f64> {
+    let mut header = [0; 8];
+    reader.read_exact(&mut header).ok()?;
+
+    let little_endian = match header[4..8] {
+        [0x4d, 0x3c, 0x2b, 0x1a] => true,
+        [0x1a, 0x2b, 0x3c, 0x4d] => false,
+        _ => return None,
+    };
+    let read_u32 = |bytes: &[u8]| to_u32(bytes, little_endian);
+    let read_u16 = |bytes: &[u8]| to_u16(bytes, little_endian);
+
+    let section_len = read_u32(&header[0..4]) as usize;
+    std::io::copy(
+        &mut reader.by_ref().take(section_len.checked_sub(12)? as u64),
+        &mut std::io::sink(),
+    )
+    .ok()?;
+
+    // Timestamp resolution of each interface, in order of their description blocks.
+    let mut resolutions = Vec::new();
+
+    for _ in 0..MAX_PCAPNG_BLOCKS {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header).ok()?;
+
+        let block_type = read_u32(&header[0..4]);
+        let block_len = read_u32(&header[4..8]) as usize;
+        if !(12..=1 << 24).contains(&block_len) {
+            return None;
+        }
+
+        let mut body = vec![0; block_len - 8];
+        reader.read_exact(&mut body).ok()?;
+
+        let timestamp = |interface: usize, high: &[u8], low: &[u8]| -> f64 {
+            let ticks = (read_u32(high) as u64) << 32 | read_u32(low) as u64;
+            ticks as f64 * resolutions.get(interface).copied().unwrap_or(1e-6)
+        };
+
+        match block_type {
+            // Interface description block, with options after the link type and snaplen.
+            1 => {
+                let mut resolution = 1e-6;
+                let mut options = body.get(8..body.len().saturating_sub(4))?;
+
+                while options.len() >= 4 {
+                    let code = read_u16(&options[0..2]);
+                    let len = read_u16(&options[2..4]) as usize;
+                    let value = options.get(4..4 + len)?;
+
+                    match code {
+                        0 => break,
+                        // if_tsresol: negative power of 10, or of 2 if the MSB is set.
+                        9 if len >= 1 => {
+                            resolution = match value[0] {
+                                v if v & 0x80 != 0 => 2f64.powi(-((v & 0x7f) as i32)),
+                                v => 10f64.powi(-(v as i32)),
+                            }
+                        }
+                        _ => {}
+                    }
+
+                    options = options
+                        .get(4 + len.next_multiple_of(4)..)
+                        .unwrap_or_default();
+                }
+
+                resolutions.push(resolution);
+            }
+            // Obsolete packet block.
+            2 if body.len() >= 12 => {
+                let interface = read_u16(&body[0..2]) as usize;
+                return Some(timestamp(interface, &body[4..8], &body[8..12]));
+            }
+            // Enhanced packet block.
+            6 if body.len() >= 12 => {
+                let interface = read_u32(&body[0..4]) as usize;
+                return Some(timestamp(interface, &body[4..8], &body[8..12]));
+            }
+            // A new section may change the byte order and interfaces.
+            0x0a0d0d0a => return None,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+pub(crate) fn to_u16(bytes: &[u8], little_endian: bool) -> u16 {
+    let bytes = bytes.try_into().unwrap();
+    match little_endian {
+        true => u16::from_le_bytes(bytes),
+        false => u16::from_be_bytes(bytes),
+    }
+}
+
+pub(crate) fn to_u32(bytes: &[u8], little_endian: bool) -> u32 {
+    let bytes = bytes.try_into().unwrap();
+    match little_endian {
+        true => u32::from_le_bytes(bytes),
+        false => u32::from_be_bytes(bytes),
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, timeout, Instant};
+
+use crate::capture::Burst;
+use crate::expression::Expression;
+use crate::output::BurstFilter;
+
+/// Time to wait before accepting clients again after accepting one failed, e.g. because
+/// the process has run out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Seconds clients are given to receive their queued bursts once the capture has ended.
+const FLUSH_TIMEOUT: f64 = 5.0;
+
+/// Bursts a client is interested in, sent by the client as a JSON object on a single line.
+///
+/// The fields are the filter options of the command line, with filter an expression.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientFilter {
+    min_bytes: Option<u32>,
+    max_bytes: Option<u32>,
+    min_packets: Option<u16>,
+    max_packets: Option<u16>,
+    min_duration: Option<f64>,
+    max_duration: Option<f64>,
+    min_rate: Option<f64>,
+    filter: Option<String>,
+}
+
+impl ClientFilter {
+    fn parse(line: &str) -> Result<BurstFilter, String> {
+        let filter: ClientFilter = serde_json::from_str(line).map_err(|err| err.to_string())?;
+        let expression = match &filter.filter {
+            Some(expression) => Some(expression.parse::<Expression>()?),
+            None => None,
+        };
+
+        Ok(BurstFilter {
+            min_bytes: filter.min_bytes,
+            max_bytes: filter.max_bytes,
+            min_packets: filter.min_packets,
+            max_packets: filter.max_packets,
+            min_duration: filter.min_duration,
+            max_duration: filter.max_duration,
+            min_rate: filter.min_rate,
+            expression,
+        })
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+/// Serves bursts as JSON lines to any number of clients connected over TCP or a Unix
+/// domain socket.
+///
+/// Each client receives the bursts matching its filter, all bursts until it sends one. A
+/// client that does not keep up loses its oldest queued bursts, and is sent a line with the
+/// number of bursts dropped before the next burst.
+pub struct Publisher {
+    listener: Option<Listener>,
+    buffer: usize,
+}
+
+impl Publisher {
+    /// Listen on a Unix domain socket if addr contains a /, or on a TCP address otherwise.
+    /// Up to buffer bursts are queued for each client.
+    pub async fn bind(addr: &str, buffer: usize) -> Result<Self, Box<dyn Error>> {
+        let listener = if addr.contains('/') {
+            let path = PathBuf::from(addr);
+            // Remove the socket of an earlier run, but no other kind of file.
+            if std::fs::metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+                std::fs::remove_file(&path)?;
+            }
+            Listener::Unix(UnixListener::bind(&path)?, path)
+        } else {
+            Listener::Tcp(TcpListener::bind(addr).await?)
+        };
+        eprintln!("Publishing bursts on {}", addr);
+
+        Ok(Publisher {
+            listener: Some(listener),
+            buffer: buffer.max(1),
+        })
+    }
+
+    pub async fn start(&mut self) -> (mpsc::Sender<Burst>, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::channel::<Burst>(100);
+
+        let listener = self.listener.take().expect("publisher already started");
+        let buffer = self.buffer;
+
+        let handle = tokio::spawn(async move {
+            let mut clients: Vec<Arc<Client>> = Vec::new();
+            let mut writers = Vec::new();
+            // Clients are not accepted until then after accepting one failed.
+            let mut accept_paused = None;
+
+            loop {
+                let accepted = tokio::select! {
+                    burst = rx.recv() => {
+                        let Some(burst) = burst else {
+                            break;
+                        };
+                        clients.retain(|client| !client.state.lock().unwrap().closed);
+
+                        let mut line = None;
+                        for client in &clients {
+                            client.publish(&burst, &mut line, buffer);
+                        }
+                        continue;
+                    },
+                    _ = sleep_until(accept_paused.unwrap_or_else(Instant::now)), if accept_paused.is_some() => {
+                        accept_paused = None;
+                        continue;
+                    },
+                    accepted = listener.accept(), if accept_paused.is_none() => accepted,
+                };
+
+                let (reader, writer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        eprintln!("Failed to accept publish client: {}", err);
+                        accept_paused = Some(Instant::now() + ACCEPT_BACKOFF);
+                        continue;
+                    }
+                };
+                let client = Arc::new(Client::default());
+                clients.push(client.clone());
+                writers.retain(|writer: &JoinHandle<()>| !writer.is_finished());
+
+                tokio::spawn(client.clone().read_filters(reader, buffer));
+                writers.push(tokio::spawn(client.write(writer)));
+            }
+
+            // Give clients the chance to receive the last bursts.
+            for client in &clients {
+                client.close();
+            }
+            let flush = async {
+                for writer in writers {
+                    writer.await.ok();
+                }
+            };
+            timeout(Duration::from_secs_f64(FLUSH_TIMEOUT), flush)
+                .await
+                .ok();
+
+            if let Listener::Unix(_, path) = &listener {
+                std::fs::remove_file(path).ok();
+            }
+        });
+
+        (tx, handle)
+    }
+}
+
+type BoxedReader = Box<dyn AsyncRead + Send + Unpin>;
+type BoxedWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+impl Listener {
+    async fn accept(&self) -> std::io::Result<(BoxedReader, BoxedWriter)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                let (reader, writer) = stream.into_split();
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+            Listener::Unix(listener, _) => {
+                let (stream, _) = listener.accept().await?;
+                let (reader, writer) = stream.into_split();
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Client {
+    state: Mutex<ClientState>,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct ClientState {
+    filter: BurstFilter,
+    /// Lines waiting to be written, oldest first.
+    queue: VecDeque<Arc<str>>,
+    /// Bursts dropped since the last line was written.
+    dropped: u64,
+    /// Set when the capture has ended or the client has disconnected.
+    closed: bool,
+}
+
+impl ClientState {
+    fn push(&mut self, line: Arc<str>, buffer: usize) {
+        if self.queue.len() >= buffer {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+        self.queue.push_back(line);
+    }
+}
+
+impl Client {
+    /// Queue a burst if it matches the filter, dropping the oldest queued line if the
+    /// queue is full. The burst is serialized once for all clients.
+    fn publish(&self, burst: &Burst, line: &mut Option<Arc<str>>, buffer: usize) {
+        let mut state = self.state.lock().unwrap();
+        if !state.filter.matches(burst) {
+            return;
+        }
+
+        let line = line.get_or_insert_with(|| {
+            let mut line = serde_json::to_string(burst).unwrap();
+            line.push('\n');
+            line.into()
+        });
+
+        state.push(line.clone(), buffer);
+        drop(state);
+
+        self.notify.notify_one();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_one();
+    }
+
+    /// Replace the filter with each line received from the client.
+    async fn read_filters(self: Arc<Self>, reader: BoxedReader, buffer: usize) {
+        let mut lines = BufReader::new(reader).lines();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut state = self.state.lock().unwrap();
+            match ClientFilter::parse(&line) {
+                Ok(filter) => state.filter = filter,
+                Err(err) => {
+                    let error = serde_json::json!({ "error": format!("invalid filter: {}", err) });
+                    state.push(format!("{}\n", error).into(), buffer);
+                    drop(state);
+                    self.notify.notify_one();
+                }
+            }
+        }
+    }
+
+    /// Write queued lines until the client disconnects, or all lines are written after
+    /// the capture has ended.
+    async fn write(self: Arc<Self>, writer: BoxedWriter) {
+        let mut writer = BufWriter::new(writer);
+
+        loop {
+            let (lines, dropped, closed) = {
+                let mut state = self.state.lock().unwrap();
+                let lines: Vec<Arc<str>> = state.queue.drain(..).collect();
+                (lines, std::mem::take(&mut state.dropped), state.closed)
+            };
+
+            if lines.is_empty() {
+                if closed {
+                    break;
+                }
+                self.notify.notified().await;
+                continue;
+            }
+
+            let mut result = Ok(());
+            if dropped > 0 {
+                let notice = serde_json::json!({ "dropped": dropped });
+                result = writer.write_all(format!("{}\n", notice).as_bytes()).await;
+            }
+            for line in lines {
+                if result.is_err() {
+                    break;
+                }
+                result = writer.write_all(line.as_bytes()).await;
+            }
+
+            if result.is_err() || writer.flush().await.is_err() {
+                break;
+            }
+        }
+
+        writer.shutdown().await.ok();
+        self.state.lock().unwrap().closed = true;
+    }
+}
@@ -0,0 +1,163 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::process::Stdio;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration};
+
+use crate::source::{Input, Tshark};
+
+/// Maximum length of the line identifying a probe.
+const MAX_HELLO_LEN: u64 = 256;
+
+/// Time to wait after failing to accept a connection, e.g. when out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Accept pcap or pcapng streams from remote probes until interrupted, and send an input
+/// for each connection.
+///
+/// A stream may be preceded by a line `PROBE <id>` to identify the probe. Otherwise, the
+/// probe is identified by its IP address.
+pub(crate) async fn listen(
+    addr: String,
+    tshark_args: Vec<String>,
+    input_tx: mpsc::Sender<Input>,
+) -> Result<(), String> {
+    let listener = TcpListener::bind(&addr)
+        .await
+        .map_err(|err| format!("failed to listen on {}: {}", addr, err))?;
+    eprintln!("Listening for probes on {}", addr);
+
+    let interrupt = tokio::signal::ctrl_c();
+    tokio::pin!(interrupt);
+
+    loop {
+        tokio::select! {
+            _ = &mut interrupt => break,
+            conn = listener.accept() => {
+                let (socket, peer) = match conn {
+                    Ok(conn) => conn,
+                    Err(err) => {
+                        eprintln!("Failed to accept probe: {}", err);
+                        tokio::select! {
+                            _ = &mut interrupt => break,
+                            _ = sleep(ACCEPT_BACKOFF) => continue,
+                        }
+                    }
+                };
+                let tshark_args = tshark_args.clone();
+                let input_tx = input_tx.clone();
+
+                tokio::spawn(async move {
+                    if let Err(err) = read_probe(socket, peer, tshark_args, input_tx).await {
+                        eprintln!("Probe {}: {}", peer, err);
+                    }
+                });
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a probe's stream with tshark until the connection is closed.
+async fn read_probe(
+    socket: TcpStream,
+    peer: SocketAddr,
+    mut tshark_args: Vec<String>,
+    input_tx: mpsc::Sender<Input>,
+) -> Result<(), String> {
+    let mut reader = BufReader::new(socket);
+
+    let probe = match read_hello(&mut reader).await? {
+        Some(probe) => probe,
+        None => peer.ip().to_string(),
+    };
+    eprintln!("Probe {} connected from {}", probe, peer);
+
+    tshark_args.extend(["-r".to_string(), "-".to_string()]);
+    let mut tshark = Tshark::spawn(&tshark_args, Stdio::piped())?;
+    let mut stdin = tshark.child.stdin.take().unwrap();
+
+    let (line_tx, line_rx) = mpsc::channel(1000);
+    let input = Input {
+        probe: Some(probe.clone()),
+        lines: line_rx,
+    };
+    if input_tx.send(input).await.is_err() {
+        return Ok(());
+    }
+
+    // Closes the input of tshark when the probe disconnects.
+    let copy = tokio::spawn(async move { tokio::io::copy_buf(&mut reader, &mut stdin).await });
+
+    while let Some(line) = tshark.next_line().await? {
+        if line_tx.send(line).await.is_err() {
+            tshark.stop();
+            break;
+        }
+    }
+
+    tshark.wait().await?;
+    copy.abort();
+    eprintln!("Probe {} disconnected", probe);
+
+    Ok(())
+}
+
+/// Read the line identifying a probe, if the stream does not start with a capture.
+async fn read_hello(reader: &mut BufReader<TcpStream>) -> Result<Option<String>, String> {
+    let buf = reader
+        .fill_buf()
+        .await
+        .map_err(|err| format!("failed to read stream: {}", err))?;
+
+    // No pcap or pcapng magic number starts with a P.
+    if !buf.starts_with(b"P") {
+        return Ok(None);
+    }
+
+    let mut line = String::new();
+    reader
+        .take(MAX_HELLO_LEN)
+        .read_line(&mut line)
+        .await
+        .map_err(|err| format!("failed to read probe ID: {}", err))?;
+
+    match line.trim_end().strip_prefix("PROBE ") {
+        Some(probe) if !probe.trim().is_empty() => Ok(Some(probe.trim().to_string())),
+        _ => Err(format!("expected PROBE <id>, got {:?}", line.trim_end())),
+    }
+}
+
+/// Capture with tshark and send the capture as a pcapng stream to a collector listening
+/// at addr, identified by probe if set. Returns when the capture is interrupted.
+pub async fn send(
+    addr: &str,
+    probe: Option<&str>,
+    tshark_args: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let mut socket = TcpStream::connect(addr)
+        .await
+        .map_err(|err| format!("failed to connect to {}: {}", addr, err))?;
+
+    if let Some(probe) = probe {
+        socket
+            .write_all(format!("PROBE {}\n", probe).as_bytes())
+            .await
+            .map_err(|err| format!("failed to send to {}: {}", addr, err))?;
+    }
+
+    let mut tshark = Tshark::spawn(tshark_args, Stdio::inherit())?;
+
+    tokio::io::copy_buf(tshark.stdout(), &mut socket)
+        .await
+        .map_err(|err| format!("failed to send to {}: {}", addr, err))?;
+
+    socket.shutdown().await.ok();
+    tshark.wait().await?;
+
+    Ok(())
+}
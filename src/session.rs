@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+
+use crate::capture::{Burst, FlowKey};
+
+/// Seconds between two bursts of a flow for them to belong to different sessions.
+const SESSION_TIMEOUT: f64 = 60.0;
+
+/// Number of most recent burst intervals used to infer the segment duration.
+const INFERENCE_WINDOW: usize = 50;
+
+/// Minimum number of burst intervals needed before inferring the segment duration.
+const MIN_INFERENCE_INTERVALS: usize = 8;
+
+/// Maximum ratio between two bitrates for them to be considered the same quality level
+/// when no bitrate ladder is configured.
+const LEVEL_RATIO: f64 = 1.3;
+
+#[derive(Debug, Clone, Default)]
+pub struct SessionOptions {
+    /// Playback duration of a segment in seconds. Inferred from the burst cadence if unset.
+    pub segment_duration: Option<f64>,
+    /// Bitrates (bits/s) of the available quality levels in ascending order. Levels are
+    /// formed from the observed bitrates if unset.
+    pub bitrate_ladder: Option<Vec<f64>>,
+}
+
+/// Direction of a quality-level switch between two consecutive segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Switch {
+    None,
+    Up,
+    Down,
+}
+
+/// A burst interpreted as a media segment of a playback session.
+#[derive(Debug, Clone)]
+pub struct SegmentReport {
+    pub src: String,
+    pub dst: String,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub interface: Option<String>,
+    pub probe: Option<String>,
+    /// Session identifier, unique for the run.
+    pub session: u32,
+    /// Index of the segment within the session, starting at 0.
+    pub index: u32,
+    pub start: f64,
+    pub end: f64,
+    pub size: u32,
+    /// Segment duration (s) used for the estimates.
+    pub duration: f64,
+    /// Estimated bitrate in bits/s.
+    pub bitrate: f64,
+    /// Quality level, the index of the ladder rung, or without a ladder the rank of the
+    /// level among the levels seen so far in the session, both starting at the lowest.
+    pub level: usize,
+    /// Bitrate (bits/s) of the quality level.
+    pub level_bitrate: f64,
+    pub switch: Switch,
+    /// Estimated seconds of media buffered when the segment completed.
+    pub buffer: f64,
+    /// Estimated total seconds of stalled playback in the session so far.
+    pub stall: f64,
+}
+
+/// Groups the bursts of each flow into playback sessions and estimates the bitrate,
+/// quality level and buffer state for every segment.
+pub struct SessionAnalyzer {
+    options: SessionOptions,
+    sessions: HashMap<FlowKey, Session>,
+    next_session: u32,
+    /// Sessions with a single burst, which cannot be analyzed without a segment duration.
+    num_unanalyzed: u32,
+}
+
+impl SessionAnalyzer {
+    pub fn new(options: SessionOptions) -> Self {
+        SessionAnalyzer {
+            options,
+            sessions: HashMap::new(),
+            next_session: 0,
+            num_unanalyzed: 0,
+        }
+    }
+
+    /// Add a burst to its flow's session and return the segments that could be analyzed.
+    ///
+    /// If the segment duration is inferred, bursts are held back until enough of them
+    /// have been seen in the session.
+    pub fn add_burst(&mut self, burst: &Burst) -> Vec<SegmentReport> {
+        let key = burst.flow_key();
+
+        let mut reports = Vec::new();
+
+        // End sessions, including this flow's, that have been inactive for too long.
+        let num_unanalyzed = &mut self.num_unanalyzed;
+        self.sessions.retain(|_, session| {
+            if burst.start - session.last_start <= SESSION_TIMEOUT {
+                return true;
+            }
+            if !session.finish(&self.options, &mut reports) {
+                *num_unanalyzed += 1;
+            }
+            false
+        });
+
+        let next_session = &mut self.next_session;
+        self.sessions
+            .entry(key)
+            .or_insert_with(|| {
+                *next_session += 1;
+                Session::new(*next_session - 1)
+            })
+            .add_burst(burst, &self.options, &mut reports);
+
+        reports
+    }
+
+    /// Analyze all bursts still held back. Sessions with a single burst and no configured
+    /// segment duration cannot be analyzed.
+    pub fn finish(&mut self) -> Vec<SegmentReport> {
+        let mut reports = Vec::new();
+
+        for (_, mut session) in self.sessions.drain() {
+            if !session.finish(&self.options, &mut reports) {
+                self.num_unanalyzed += 1;
+            }
+        }
+
+        reports.sort_by(|a, b| a.end.total_cmp(&b.end));
+        reports
+    }
+
+    /// Number of sessions that ended without being analyzed, as they had a single burst
+    /// and no configured segment duration.
+    pub fn num_unanalyzed(&self) -> u32 {
+        self.num_unanalyzed
+    }
+}
+
+struct Session {
+    id: u32,
+    index: u32,
+    last_start: f64,
+    intervals: Vec<f64>,
+    pending: Vec<Burst>,
+    levels: Vec<f64>,
+    last_level: Option<f64>,
+    last_end: Option<f64>,
+    buffer: f64,
+    stall: f64,
+}
+
+impl Session {
+    fn new(id: u32) -> Self {
+        Session {
+            id,
+            index: 0,
+            last_start: f64::NAN,
+            intervals: Vec::new(),
+            pending: Vec::new(),
+            levels: Vec::new(),
+            last_level: None,
+            last_end: None,
+            buffer: 0.0,
+            stall: 0.0,
+        }
+    }
+
+    fn add_burst(&mut self, burst: &Burst, options: &SessionOptions, out: &mut Vec<SegmentReport>) {
+        if !self.last_start.is_nan() {
+            if self.intervals.len() == INFERENCE_WINDOW {
+                self.intervals.remove(0);
+            }
+            self.intervals.push(burst.start - self.last_start);
+        }
+        self.last_start = burst.start;
+        self.pending.push(burst.clone());
+
+        if options.segment_duration.is_some() || self.intervals.len() >= MIN_INFERENCE_INTERVALS {
+            self.analyze_pending(options, out);
+        }
+    }
+
+    /// Analyze the bursts still held back. Returns false if they cannot be analyzed.
+    fn finish(&mut self, options: &SessionOptions, out: &mut Vec<SegmentReport>) -> bool {
+        if options.segment_duration.is_none() && self.intervals.is_empty() {
+            return false;
+        }
+        self.analyze_pending(options, out);
+        true
+    }
+
+    /// Segment duration from the configuration, otherwise the upper quartile of the
+    /// intervals between bursts. Once the buffer is full, players request one segment per
+    /// segment duration, while segments are requested back-to-back when filling it.
+    fn segment_duration(&self, options: &SessionOptions) -> f64 {
+        if let Some(duration) = options.segment_duration {
+            return duration;
+        }
+
+        let mut intervals = self.intervals.clone();
+        intervals.sort_by(f64::total_cmp);
+        intervals[intervals.len() * 3 / 4]
+    }
+
+    fn analyze_pending(&mut self, options: &SessionOptions, out: &mut Vec<SegmentReport>) {
+        let duration = self.segment_duration(options);
+
+        for burst in std::mem::take(&mut self.pending) {
+            let bitrate = burst.size as f64 * 8.0 / duration;
+            let (level, level_bitrate) = self.level(bitrate, options);
+
+            let switch = match self.last_level {
+                Some(last) if level_bitrate > last => Switch::Up,
+                Some(last) if level_bitrate < last => Switch::Down,
+                _ => Switch::None,
+            };
+            self.last_level = Some(level_bitrate);
+
+            // Playback starts with the first segment and drains the buffer in real time.
+            if let Some(last_end) = self.last_end {
+                self.buffer -= burst.end - last_end;
+                if self.buffer < 0.0 {
+                    self.stall -= self.buffer;
+                    self.buffer = 0.0;
+                }
+            }
+            self.buffer += duration;
+            self.last_end = Some(burst.end);
+
+            out.push(SegmentReport {
+                src: burst.src,
+                dst: burst.dst,
+                src_port: burst.src_port,
+                dst_port: burst.dst_port,
+                interface: burst.interface,
+                probe: burst.probe,
+                session: self.id,
+                index: self.index,
+                start: burst.start,
+                end: burst.end,
+                size: burst.size,
+                duration,
+                bitrate,
+                level,
+                level_bitrate,
+                switch,
+                buffer: self.buffer,
+                stall: self.stall,
+            });
+
+            self.index += 1;
+        }
+    }
+
+    /// Map a bitrate to the closest ladder rung, or to a level formed from the bitrates
+    /// seen so far in the session, ranked from the lowest.
+    fn level(&mut self, bitrate: f64, options: &SessionOptions) -> (usize, f64) {
+        let closest = |levels: &[f64]| -> Option<usize> {
+            (0..levels.len()).min_by(|&a, &b| {
+                let a = (bitrate / levels[a]).ln().abs();
+                let b = (bitrate / levels[b]).ln().abs();
+                a.total_cmp(&b)
+            })
+        };
+
+        if let Some(ladder) = options.bitrate_ladder.as_ref().filter(|l| !l.is_empty()) {
+            let level = closest(ladder).unwrap();
+            return (level, ladder[level]);
+        }
+
+        let level = match closest(&self.levels) {
+            Some(level) if (bitrate / self.levels[level]).ln().abs() < LEVEL_RATIO.ln() => level,
+            _ => {
+                // Levels are kept in ascending order, so a lower level seen later raises
+                // the rank of the levels above it.
+                let level = self.levels.partition_point(|&l| l < bitrate);
+                self.levels.insert(level, bitrate);
+                level
+            }
+        };
+
+        (level, self.levels[level])
+    }
+}
@@ -0,0 +1,669 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use std::future::Future;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::{interval_at, sleep_until, timeout, Duration, Instant, MissedTickBehavior};
+
+use crate::capture::Burst;
+
+/// Seconds to wait before the first retry of a failed batch. Doubled for every failure.
+const MIN_BACKOFF: f64 = 1.0;
+
+/// Maximum seconds between retries.
+const MAX_BACKOFF: f64 = 60.0;
+
+/// Seconds for a request to complete, including connecting.
+const REQUEST_TIMEOUT: f64 = 30.0;
+
+/// Batches kept in memory while the collector is unavailable, without a spool directory.
+const MAX_MEMORY_BATCHES: usize = 1000;
+
+/// URL of an HTTP collector.
+#[derive(Debug, Clone)]
+struct Url {
+    /// Host and port as given, for the Host header.
+    authority: String,
+    /// Address to connect to.
+    addr: String,
+    path: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Self, Box<dyn Error>> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err(format!("unsupported URL {}, only http:// is supported", url).into());
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(format!("no host in URL {}", url).into());
+        }
+
+        // An IPv6 address in brackets has colons but no port.
+        let has_port = authority
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.contains(']'));
+        let addr = match has_port {
+            true => authority.to_string(),
+            false => format!("{}:80", authority),
+        };
+
+        Ok(Url {
+            authority: authority.to_string(),
+            addr,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Batches that have not been delivered yet, oldest first.
+enum Spool {
+    Memory(VecDeque<(String, Vec<u8>)>),
+    /// A file per batch, named so that they sort in the order they were created.
+    Disk {
+        dir: PathBuf,
+        ids: VecDeque<String>,
+    },
+}
+
+impl Spool {
+    async fn push(&mut self, id: String, body: Vec<u8>) -> std::io::Result<()> {
+        match self {
+            Spool::Memory(batches) => {
+                if batches.len() == MAX_MEMORY_BATCHES {
+                    batches.pop_front();
+                    eprintln!("Too many undelivered batches, dropped the oldest");
+                }
+                batches.push_back((id, body));
+            }
+            Spool::Disk { dir, ids } => {
+                // Written under a temporary name so that no partial batch is sent.
+                let tmp = dir.join(format!("{}.tmp", id));
+                let path = dir.join(format!("{}.json", id));
+                blocking(move || {
+                    std::fs::write(&tmp, body)?;
+                    std::fs::rename(tmp, path)
+                })
+                .await?;
+                ids.push_back(id);
+            }
+        }
+        Ok(())
+    }
+
+    async fn oldest(&self) -> std::io::Result<Option<(String, Vec<u8>)>> {
+        match self {
+            Spool::Memory(batches) => Ok(batches.front().cloned()),
+            Spool::Disk { dir, ids } => {
+                let Some(id) = ids.front() else {
+                    return Ok(None);
+                };
+                let path = dir.join(format!("{}.json", id));
+                let body = blocking(move || std::fs::read(path)).await?;
+                Ok(Some((id.clone(), body)))
+            }
+        }
+    }
+
+    async fn remove(&mut self, id: &str) -> std::io::Result<()> {
+        match self {
+            Spool::Memory(batches) => batches.retain(|(batch_id, _)| batch_id != id),
+            Spool::Disk { dir, ids } => {
+                let path = dir.join(format!("{}.json", id));
+                blocking(move || std::fs::remove_file(path)).await?;
+                ids.retain(|batch_id| batch_id != id);
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Spool::Memory(batches) => batches.len(),
+            Spool::Disk { ids, .. } => ids.len(),
+        }
+    }
+}
+
+/// Run file operations on the blocking thread pool, so that they do not stall the capture.
+async fn blocking<T, F>(f: F) -> std::io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> std::io::Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|err| Err(std::io::Error::other(err)))
+}
+
+/// IDs of the batches in a spool directory, oldest first. Removes batches that were not
+/// completely written, e.g. when a previous run was killed.
+fn spooled_ids(dir: &Path) -> std::io::Result<VecDeque<String>> {
+    let mut ids = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => {
+                if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    ids.push(id.to_string());
+                }
+            }
+            Some("tmp") => std::fs::remove_file(&path)?,
+            _ => {}
+        }
+    }
+    ids.sort();
+    Ok(ids.into())
+}
+
+/// Opens connections to the collector.
+trait Connect: Send + Sync + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send;
+
+    fn connect(&self, addr: &str) -> impl Future<Output = std::io::Result<Self::Stream>> + Send;
+}
+
+struct Tcp;
+
+impl Connect for Tcp {
+    type Stream = TcpStream;
+
+    async fn connect(&self, addr: &str) -> std::io::Result<TcpStream> {
+        TcpStream::connect(addr).await
+    }
+}
+
+struct Shared {
+    spool: Mutex<Spool>,
+    /// Notified when a batch is spooled or the capture ends.
+    notify: Notify,
+    closed: AtomicBool,
+}
+
+/// Posts bursts in batches, as JSON arrays, to an HTTP collector.
+///
+/// Batches that cannot be delivered are retried with exponential backoff. They are kept in
+/// a spool directory if set, so that they survive restarts, and in memory otherwise.
+pub struct HttpSink {
+    url: Url,
+    batch_size: usize,
+    interval: f64,
+    spool_dir: Option<PathBuf>,
+    /// Batches in the spool directory when it was opened.
+    spooled: VecDeque<String>,
+}
+
+impl HttpSink {
+    /// A batch is posted when it has batch_size bursts, or interval seconds after the last
+    /// batch if it has any bursts.
+    pub fn new(url: &str, batch_size: usize, interval: f64) -> Result<Self, Box<dyn Error>> {
+        if interval.is_nan() || interval <= 0.0 {
+            return Err("the sink interval must be positive".into());
+        }
+
+        Ok(HttpSink {
+            url: Url::parse(url)?,
+            batch_size: batch_size.max(1),
+            interval,
+            spool_dir: None,
+            spooled: VecDeque::new(),
+        })
+    }
+
+    /// Keep undelivered batches in dir, and send those of earlier runs first.
+    pub fn spool(mut self, dir: &str) -> Result<Self, Box<dyn Error>> {
+        std::fs::create_dir_all(dir)
+            .map_err(|err| format!("failed to create spool directory {}: {}", dir, err))?;
+        self.spooled = spooled_ids(Path::new(dir))
+            .map_err(|err| format!("failed to read spool directory {}: {}", dir, err))?;
+        self.spool_dir = Some(PathBuf::from(dir));
+        Ok(self)
+    }
+
+    pub async fn start(&mut self) -> (mpsc::Sender<Burst>, JoinHandle<()>) {
+        self.start_with(Tcp).await
+    }
+
+    async fn start_with<C: Connect>(
+        &mut self,
+        connector: C,
+    ) -> (mpsc::Sender<Burst>, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::channel::<Burst>(100);
+
+        let spool = match &self.spool_dir {
+            Some(dir) => Spool::Disk {
+                dir: dir.clone(),
+                ids: std::mem::take(&mut self.spooled),
+            },
+            None => Spool::Memory(VecDeque::new()),
+        };
+        let spooled = spool.len();
+        if spooled > 0 {
+            eprintln!("Sending {} spooled batches", spooled);
+        }
+
+        let shared = Arc::new(Shared {
+            spool: Mutex::new(spool),
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+        });
+        let batch_size = self.batch_size;
+        let period = Duration::from_secs_f64(self.interval);
+
+        let delivery = tokio::spawn(deliver(connector, self.url.clone(), shared.clone()));
+
+        let handle = tokio::spawn(async move {
+            let mut batch = Vec::new();
+            let mut ticker = interval_at(Instant::now() + period, period);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut seq = 0u64;
+
+            loop {
+                let ready = tokio::select! {
+                    burst = rx.recv() => match burst {
+                        Some(burst) => {
+                            batch.push(burst);
+                            batch.len() >= batch_size
+                        },
+                        None => break,
+                    },
+                    _ = ticker.tick() => !batch.is_empty(),
+                };
+
+                if ready {
+                    spool_batch(&shared, &mut batch, &mut seq).await;
+                    ticker.reset();
+                }
+            }
+
+            if !batch.is_empty() {
+                spool_batch(&shared, &mut batch, &mut seq).await;
+            }
+
+            shared.closed.store(true, Ordering::Relaxed);
+            shared.notify.notify_one();
+            delivery.await.ok();
+        });
+
+        (tx, handle)
+    }
+}
+
+async fn spool_batch(shared: &Shared, batch: &mut Vec<Burst>, seq: &mut u64) {
+    let body = serde_json::to_vec(&batch).unwrap();
+    batch.clear();
+
+    let time = SystemTime::UNIX_EPOCH.elapsed().unwrap_or_default();
+    let id = format!("{:020}-{:06}", time.as_nanos(), seq);
+    *seq += 1;
+
+    if let Err(err) = shared.spool.lock().await.push(id, body).await {
+        eprintln!("Failed to spool bursts: {}", err);
+        return;
+    }
+    shared.notify.notify_one();
+}
+
+/// Post spooled batches in order until the capture has ended and all are delivered, or
+/// delivery fails after the capture has ended.
+async fn deliver<C: Connect>(connector: C, url: Url, shared: Arc<Shared>) {
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        let oldest = shared.spool.lock().await.oldest().await;
+        let (id, body) = match oldest {
+            Ok(Some(batch)) => batch,
+            Ok(None) if shared.closed.load(Ordering::Relaxed) => break,
+            Ok(None) => {
+                shared.notify.notified().await;
+                continue;
+            }
+            Err(err) => {
+                eprintln!("Failed to read spooled bursts: {}", err);
+                break;
+            }
+        };
+
+        let result = timeout(
+            Duration::from_secs_f64(REQUEST_TIMEOUT),
+            post(&connector, &url, &body),
+        )
+        .await
+        .unwrap_or_else(|_| Err("request timed out".into()));
+
+        match result {
+            Ok(()) => {
+                if let Err(err) = shared.spool.lock().await.remove(&id).await {
+                    eprintln!("Failed to remove spooled bursts: {}", err);
+                    break;
+                }
+                backoff = MIN_BACKOFF;
+            }
+            Err(err) if shared.closed.load(Ordering::Relaxed) => {
+                let remaining = match &*shared.spool.lock().await {
+                    Spool::Disk { dir, ids } => {
+                        format!("{} batches left in {}", ids.len(), dir.display())
+                    }
+                    spool => format!("{} batches not delivered", spool.len()),
+                };
+                eprintln!(
+                    "Failed to send bursts to http://{}{}: {}, {}",
+                    url.authority, url.path, err, remaining
+                );
+                break;
+            }
+            Err(err) => {
+                eprintln!(
+                    "Failed to send bursts to http://{}{}: {}, retrying in {} s",
+                    url.authority, url.path, err, backoff
+                );
+
+                // Wait for the backoff, or until the capture ends to make a last attempt.
+                let deadline = Instant::now() + Duration::from_secs_f64(backoff);
+                loop {
+                    tokio::select! {
+                        _ = sleep_until(deadline) => break,
+                        _ = shared.notify.notified() => {
+                            if shared.closed.load(Ordering::Relaxed) {
+                                break;
+                            }
+                        },
+                    }
+                }
+                backoff = (backoff * 2.0).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Post a JSON body and check that the collector accepted it.
+async fn post<C: Connect>(
+    connector: &C,
+    url: &Url,
+    body: &[u8],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut stream = connector.connect(&url.addr).await?;
+
+    let head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        url.path,
+        url.authority,
+        body.len(),
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status).await?;
+
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        Some(_) => Err(format!("collector responded {}", status.trim_end()).into()),
+        None => Err("no response from collector".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::{duplex, AsyncReadExt, DuplexStream};
+
+    fn burst(src_port: u16) -> Burst {
+        Burst {
+            src: "10.0.0.1".to_string(),
+            dst: "10.0.0.2".to_string(),
+            src_port,
+            dst_port: 5000,
+            start: 1.0,
+            end: 2.0,
+            num_packets: 10,
+            size: 10000,
+            interface: None,
+            probe: None,
+            wlan: None,
+        }
+    }
+
+    /// Source ports of the bursts of a posted batch.
+    fn ports(body: &[u8]) -> Vec<u64> {
+        let bursts: Vec<serde_json::Value> = serde_json::from_slice(body).unwrap();
+        bursts
+            .iter()
+            .map(|b| b["src_port"].as_u64().unwrap())
+            .collect()
+    }
+
+    /// A collector served in memory, since paused time would skip ahead while waiting for
+    /// a socket.
+    #[derive(Clone)]
+    struct Collector {
+        streams: mpsc::Sender<DuplexStream>,
+        /// Connections are closed without a response while the collector is down.
+        up: Arc<AtomicBool>,
+    }
+
+    impl Connect for Collector {
+        type Stream = DuplexStream;
+
+        async fn connect(&self, _addr: &str) -> std::io::Result<DuplexStream> {
+            let (client, server) = duplex(1 << 16);
+            self.streams
+                .send(server)
+                .await
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::ConnectionRefused))?;
+            Ok(client)
+        }
+    }
+
+    /// Accept posts, responding with the given statuses and then 200. Returns when and
+    /// what was posted.
+    fn collector(statuses: Vec<u16>) -> (Collector, mpsc::Receiver<(Instant, Vec<u8>)>) {
+        let (stream_tx, mut stream_rx) = mpsc::channel(100);
+        let up = Arc::new(AtomicBool::new(true));
+        let (tx, rx) = mpsc::channel(100);
+        let mut statuses = statuses.into_iter();
+
+        let collector = Collector {
+            streams: stream_tx,
+            up: up.clone(),
+        };
+        tokio::spawn(async move {
+            while let Some(stream) = stream_rx.recv().await {
+                if !up.load(Ordering::Relaxed) {
+                    continue;
+                }
+
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).await.unwrap();
+
+                // Recorded before responding, so that it is seen once the sink is done.
+                if tx.send((Instant::now(), body)).await.is_err() {
+                    break;
+                }
+                let status = statuses.next().unwrap_or(200);
+                let response = format!("HTTP/1.1 {} Status\r\nContent-Length: 0\r\n\r\n", status);
+                reader
+                    .get_mut()
+                    .write_all(response.as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+
+        (collector, rx)
+    }
+
+    fn spool_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("burstshark-test-{}-{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn posts_bursts_in_batches() {
+        let (collector, mut posted) = collector(Vec::new());
+
+        let mut sink = HttpSink::new("http://collector/bursts", 2, 60.0).unwrap();
+        let (tx, handle) = sink.start_with(collector).await;
+        for port in 1..=5 {
+            tx.send(burst(port)).await.unwrap();
+        }
+        drop(tx);
+        handle.await.unwrap();
+
+        // The last, partial batch is posted when the capture ends.
+        let mut batches = Vec::new();
+        while let Ok((_, body)) = posted.try_recv() {
+            batches.push(ports(&body));
+        }
+        assert_eq!(batches, vec![vec![1, 2], vec![3, 4], vec![5]]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn posts_partial_batch_after_interval() {
+        let (collector, mut posted) = collector(Vec::new());
+
+        let mut sink = HttpSink::new("http://collector/", 100, 0.2).unwrap();
+        let (tx, handle) = sink.start_with(collector).await;
+        tx.send(burst(1)).await.unwrap();
+
+        let (_, body) = timeout(Duration::from_secs(5), posted.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ports(&body), vec![1]);
+
+        drop(tx);
+        handle.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_failed_batch_with_backoff() {
+        let (collector, mut posted) = collector(vec![503, 500]);
+
+        let mut sink = HttpSink::new("http://collector/", 1, 60.0).unwrap();
+        let (tx, handle) = sink.start_with(collector).await;
+        tx.send(burst(1)).await.unwrap();
+        tx.send(burst(2)).await.unwrap();
+
+        let mut attempts = Vec::new();
+        for _ in 0..4 {
+            let attempt = timeout(Duration::from_secs(10), posted.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            attempts.push(attempt);
+        }
+        drop(tx);
+        handle.await.unwrap();
+
+        // The first batch is retried until accepted, before the second is sent.
+        let batches: Vec<_> = attempts.iter().map(|(_, body)| ports(body)).collect();
+        assert_eq!(batches, vec![vec![1], vec![1], vec![1], vec![2]]);
+
+        // Waiting MIN_BACKOFF after the first failure, and twice as long after the second.
+        let first = attempts[1].0 - attempts[0].0;
+        let second = attempts[2].0 - attempts[1].0;
+        assert_eq!(first, Duration::from_secs_f64(MIN_BACKOFF));
+        assert_eq!(second, Duration::from_secs_f64(2.0 * MIN_BACKOFF));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_spooled_batches_once_collector_is_back() {
+        let (collector, mut posted) = collector(Vec::new());
+        let dir = spool_dir("spool");
+
+        // A batch left half written by an earlier run is removed.
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("0-000000.tmp"), b"[").unwrap();
+
+        // The collector is down, so the batches stay in the spool directory.
+        collector.up.store(false, Ordering::Relaxed);
+        let mut sink = HttpSink::new("http://collector/", 1, 60.0)
+            .unwrap()
+            .spool(dir.to_str().unwrap())
+            .unwrap();
+        let (tx, handle) = sink.start_with(collector.clone()).await;
+        for port in 1..=3 {
+            tx.send(burst(port)).await.unwrap();
+        }
+        drop(tx);
+        handle.await.unwrap();
+        assert_eq!(spooled_ids(&dir).unwrap().len(), 3);
+        assert!(!dir.join("0-000000.tmp").exists());
+
+        // The next run sends them first, in order.
+        collector.up.store(true, Ordering::Relaxed);
+        let mut sink = HttpSink::new("http://collector/", 1, 60.0)
+            .unwrap()
+            .spool(dir.to_str().unwrap())
+            .unwrap();
+        let (tx, handle) = sink.start_with(collector).await;
+        tx.send(burst(4)).await.unwrap();
+        drop(tx);
+        handle.await.unwrap();
+
+        let mut batches = Vec::new();
+        while let Ok((_, body)) = posted.try_recv() {
+            batches.push(ports(&body));
+        }
+        assert_eq!(batches, vec![vec![1], vec![2], vec![3], vec![4]]);
+        assert!(spooled_ids(&dir).unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_batches_in_memory_until_collector_is_back() {
+        let (collector, mut posted) = collector(Vec::new());
+        collector.up.store(false, Ordering::Relaxed);
+
+        let mut sink = HttpSink::new("http://collector/", 1, 60.0).unwrap();
+        let (tx, handle) = sink.start_with(collector.clone()).await;
+        tx.send(burst(1)).await.unwrap();
+        tx.send(burst(2)).await.unwrap();
+
+        // Come back while the sink backs off after the first attempt.
+        sleep_until(Instant::now() + Duration::from_secs_f64(MIN_BACKOFF / 2.0)).await;
+        collector.up.store(true, Ordering::Relaxed);
+
+        let mut batches = Vec::new();
+        for _ in 0..2 {
+            let (_, body) = timeout(Duration::from_secs(10), posted.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            batches.push(ports(&body));
+        }
+        drop(tx);
+        handle.await.unwrap();
+
+        assert_eq!(batches, vec![vec![1], vec![2]]);
+    }
+}
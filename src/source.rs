@@ -0,0 +1,604 @@
+use std::error::Error;
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+
+use crate::pcap::{first_timestamp, Format, RecordKind, RecordReader};
+use crate::remote::listen;
+
+/// Interval at which followed files are checked for new packets and rotation.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where tshark reads packets from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Live capture on the interfaces in the tshark arguments.
+    Live,
+    /// Capture files. Packets of all files are merged in timestamp order.
+    Files(Vec<PathBuf>),
+    /// Follow the newest pcap or pcapng file matching any of the patterns as it grows,
+    /// and switch to newer files as they are created, e.g. by a ring buffer.
+    Follow(Vec<String>),
+    /// A pcap or pcapng stream from standard input (-) or a named pipe, which may be live
+    /// or replayed.
+    Stream(String),
+    /// pcap or pcapng streams of remote probes connecting to this address over TCP.
+    Listen(String),
+}
+
+/// Lines of tshark output of a single capture, whose packets have their own flow table.
+pub(crate) struct Input {
+    /// ID of the remote probe that captured the packets.
+    pub probe: Option<String>,
+    pub lines: mpsc::Receiver<String>,
+}
+
+/// How the time at which bursts and flows time out is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    /// Program time, for packets read as they are captured.
+    Wall,
+    /// Packet timestamps, for packets read faster than they were captured.
+    Capture,
+    /// Packet timestamps, advanced by the program time since the last packet was read
+    /// while no packets arrive.
+    Stream,
+}
+
+impl Source {
+    pub fn clock(&self) -> Clock {
+        match self {
+            Source::Live | Source::Follow(_) => Clock::Wall,
+            Source::Files(_) => Clock::Capture,
+            Source::Stream(_) | Source::Listen(_) => Clock::Stream,
+        }
+    }
+
+    /// Start tshark and send an input for each capture to the returned receiver. All
+    /// sources but remote probes have a single input. The handle completes when all
+    /// packets have been read or the capture is interrupted.
+    pub(crate) fn start(
+        &self,
+        tshark_args: &[String],
+    ) -> (mpsc::Receiver<Input>, JoinHandle<Result<(), String>>) {
+        let (input_tx, input_rx) = mpsc::channel(100);
+        let tshark_args = tshark_args.to_vec();
+
+        if let Source::Listen(addr) = self {
+            let handle = tokio::spawn(listen(addr.clone(), tshark_args, input_tx));
+            return (input_rx, handle);
+        }
+
+        let (line_tx, line_rx) = mpsc::channel(1000);
+        let input = Input {
+            probe: None,
+            lines: line_rx,
+        };
+        input_tx.try_send(input).unwrap();
+
+        let handle = match self {
+            Source::Live => tokio::spawn(read_tshark(tshark_args, line_tx)),
+            Source::Stream(path) => {
+                let mut tshark_args = tshark_args;
+                tshark_args.extend(["-r".to_string(), path.clone()]);
+                tokio::spawn(read_tshark(tshark_args, line_tx))
+            }
+            Source::Follow(patterns) => {
+                tokio::spawn(follow_files(patterns.clone(), tshark_args, line_tx))
+            }
+            Source::Files(paths) => {
+                let mut files: Vec<(Option<f64>, PathBuf)> = paths
+                    .iter()
+                    .map(|path| (first_timestamp(path), path.clone()))
+                    .collect();
+
+                // Files whose first timestamp is unknown are opened first, as they may
+                // contain the earliest packets.
+                files.sort_by(|(a, _), (b, _)| {
+                    a.unwrap_or(f64::NEG_INFINITY)
+                        .total_cmp(&b.unwrap_or(f64::NEG_INFINITY))
+                });
+
+                tokio::spawn(read_files(files, tshark_args, line_tx))
+            }
+            Source::Listen(_) => unreachable!(),
+        };
+
+        (input_rx, handle)
+    }
+}
+
+/// A tshark process whose output is read line by line.
+pub(crate) struct Tshark {
+    pub child: Child,
+    lines: Lines<BufReader<ChildStdout>>,
+    /// Terminates tshark on Ctrl-C.
+    interrupt_handle: JoinHandle<()>,
+}
+
+impl Tshark {
+    pub fn spawn(args: &[String], stdin: Stdio) -> Result<Self, String> {
+        let mut child = Command::new("tshark")
+            .args(args)
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| format!("failed to start tshark: {}", err))?;
+
+        let pid = child.id();
+        let interrupt_handle = tokio::spawn(async move {
+            tokio::signal::ctrl_c().await.unwrap();
+            if let Some(pid) = pid {
+                // tshark may already have exited on the same signal.
+                let _ = kill(Pid::from_raw(pid as i32), Signal::SIGTERM);
+            }
+        });
+
+        let lines = BufReader::new(child.stdout.take().unwrap()).lines();
+
+        Ok(Tshark {
+            child,
+            lines,
+            interrupt_handle,
+        })
+    }
+
+    pub async fn next_line(&mut self) -> Result<Option<String>, String> {
+        self.lines
+            .next_line()
+            .await
+            .map_err(|err| format!("failed to read tshark output: {}", err))
+    }
+
+    /// Raw output, for a capture written to standard output.
+    pub fn stdout(&mut self) -> &mut BufReader<ChildStdout> {
+        self.lines.get_mut()
+    }
+
+    /// Stop tshark when its output is no longer read, e.g. as the capture's output stopped.
+    pub fn stop(&mut self) {
+        let _ = self.child.start_kill();
+    }
+
+    pub async fn wait(&mut self) -> Result<(), String> {
+        self.child
+            .wait()
+            .await
+            .map_err(|err| format!("failed to wait for tshark: {}", err))?;
+        Ok(())
+    }
+}
+
+impl Drop for Tshark {
+    fn drop(&mut self) {
+        self.interrupt_handle.abort();
+    }
+}
+
+/// Read the output of a single tshark process.
+async fn read_tshark(
+    tshark_args: Vec<String>,
+    line_tx: mpsc::Sender<String>,
+) -> Result<(), String> {
+    let mut tshark = Tshark::spawn(&tshark_args, Stdio::inherit())?;
+
+    while let Some(line) = tshark.next_line().await? {
+        if line_tx.send(line).await.is_err() {
+            tshark.stop();
+            break;
+        }
+    }
+
+    tshark.wait().await
+}
+
+/// A capture file being read, with its next packet.
+struct FileReader {
+    path: String,
+    tshark: Tshark,
+    time: f64,
+    line: String,
+    num_packets: u64,
+}
+
+impl FileReader {
+    /// Start reading a file. Returns None if it has no packets.
+    async fn open(path: String, tshark_args: &[String]) -> Result<Option<Self>, String> {
+        let mut args = tshark_args.to_vec();
+        args.extend(["-r".to_string(), path.clone()]);
+
+        let mut tshark = Tshark::spawn(&args, Stdio::null())?;
+
+        match tshark.next_line().await? {
+            Some(line) => Ok(Some(FileReader {
+                path,
+                tshark,
+                time: line_time(&line),
+                line,
+                num_packets: 1,
+            })),
+            None => {
+                tshark.wait().await?;
+                eprintln!("Read 0 packets from {}", path);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Return the next packet and read the one after it. Returns None as the second
+    /// element once the file has been read.
+    async fn advance(mut self) -> Result<(String, Option<Self>), String> {
+        match self.tshark.next_line().await? {
+            Some(line) => {
+                self.num_packets += 1;
+                self.time = line_time(&line);
+                let line = std::mem::replace(&mut self.line, line);
+                Ok((line, Some(self)))
+            }
+            None => {
+                self.tshark.wait().await?;
+                eprintln!("Read {} packets from {}", self.num_packets, self.path);
+                Ok((self.line, None))
+            }
+        }
+    }
+}
+
+/// Merge the packets of files sorted by their first timestamp. A file is only opened once
+/// the files already open have no packet earlier than its first one, so that few tshark
+/// processes run at a time when the files do not overlap.
+async fn read_files(
+    files: Vec<(Option<f64>, PathBuf)>,
+    tshark_args: Vec<String>,
+    line_tx: mpsc::Sender<String>,
+) -> Result<(), String> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let interrupt_handle = {
+        let interrupted = interrupted.clone();
+        tokio::spawn(async move {
+            tokio::signal::ctrl_c().await.unwrap();
+            interrupted.store(true, Ordering::Relaxed);
+        })
+    };
+
+    let num_files = files.len();
+    let mut pending = files.into_iter().enumerate().peekable();
+    let mut readers = Vec::<FileReader>::new();
+
+    let result = loop {
+        let next_time = readers.iter().map(|r| r.time).min_by(f64::total_cmp);
+
+        if let Some((_, (first_time, _))) = pending.peek() {
+            let earlier = match (first_time, next_time) {
+                (Some(first_time), Some(next_time)) => *first_time <= next_time,
+                _ => true,
+            };
+
+            if earlier && !interrupted.load(Ordering::Relaxed) {
+                let (idx, (_, path)) = pending.next().unwrap();
+                let path = path.to_string_lossy().into_owned();
+                eprintln!("Reading file {}/{}: {}", idx + 1, num_files, path);
+
+                match FileReader::open(path, &tshark_args).await {
+                    Ok(Some(reader)) => readers.push(reader),
+                    Ok(None) => {}
+                    Err(err) => break Err(err),
+                }
+                continue;
+            }
+        }
+
+        let Some(idx) =
+            (0..readers.len()).min_by(|&a, &b| readers[a].time.total_cmp(&readers[b].time))
+        else {
+            break Ok(());
+        };
+
+        let (line, reader) = match readers.swap_remove(idx).advance().await {
+            Ok(advanced) => advanced,
+            Err(err) => break Err(err),
+        };
+        readers.extend(reader);
+
+        if line_tx.send(line).await.is_err() {
+            break Ok(());
+        }
+    };
+
+    interrupt_handle.abort();
+    result
+}
+
+/// Timestamp of a line of tshark output, the first field of every line.
+fn line_time(line: &str) -> f64 {
+    line.split(['\t', ' '])
+        .next()
+        .and_then(|time| time.parse().ok())
+        .unwrap_or(f64::NEG_INFINITY)
+}
+
+/// Follow files by writing their records to tshark reading a pcap or pcapng stream from
+/// standard input.
+async fn follow_files(
+    patterns: Vec<String>,
+    mut tshark_args: Vec<String>,
+    line_tx: mpsc::Sender<String>,
+) -> Result<(), String> {
+    tshark_args.extend(["-r".to_string(), "-".to_string()]);
+
+    let mut tshark = Tshark::spawn(&tshark_args, Stdio::piped())?;
+    let stdin = tshark.child.stdin.take().unwrap();
+    let writer = tokio::spawn(write_followed(patterns, stdin));
+
+    while let Some(line) = tshark.next_line().await? {
+        if line_tx.send(line).await.is_err() {
+            tshark.stop();
+            break;
+        }
+    }
+
+    tshark.wait().await?;
+
+    // The writer only ends by itself on an error, which also ends the input of tshark.
+    writer.abort();
+    match writer.await {
+        Ok(result) => result,
+        Err(_) => Ok(()),
+    }
+}
+
+async fn write_followed(patterns: Vec<String>, mut stdin: ChildStdin) -> Result<(), String> {
+    let mut stream = FollowedStream::default();
+    let mut current: Option<FollowedFile> = None;
+
+    loop {
+        let newest = newest_file(&patterns)?;
+
+        let Some(file) = current.as_mut() else {
+            match newest {
+                Some(path) => {
+                    eprintln!("Following {}", path.display());
+                    // Only packets written from now on are read.
+                    current = Some(FollowedFile::open(path, true).await?);
+                }
+                None => sleep(FOLLOW_INTERVAL).await,
+            }
+            continue;
+        };
+
+        // Checked before reading, so that a rotated file is read to its end before
+        // switching to the next.
+        let rotated = match &newest {
+            Some(newest) => file.is_rotated(newest).await,
+            None => false,
+        };
+
+        if !file.forward(&mut stdin, &mut stream).await? {
+            // tshark has exited.
+            return Ok(());
+        }
+
+        match newest.filter(|_| rotated) {
+            Some(path) => {
+                eprintln!("Following {}", path.display());
+                current = Some(FollowedFile::open(path, false).await?);
+            }
+            None => sleep(FOLLOW_INTERVAL).await,
+        }
+    }
+}
+
+/// The newest file matching any of the patterns, by modification time and name.
+fn newest_file(patterns: &[String]) -> Result<Option<PathBuf>, String> {
+    let mut newest = None;
+
+    for pattern in patterns {
+        for path in find_files(pattern)? {
+            let Ok(modified) = fs::metadata(&path).and_then(|meta| meta.modified()) else {
+                continue;
+            };
+            if newest
+                .as_ref()
+                .is_none_or(|(time, newest)| (modified, &path) > (*time, newest))
+            {
+                newest = Some((modified, path));
+            }
+        }
+    }
+
+    Ok(newest.map(|(_, path)| path))
+}
+
+/// Format and pcap file header of the stream written to tshark, which all followed files
+/// must share.
+#[derive(Default)]
+struct FollowedStream {
+    format: Option<Format>,
+    pcap_header: Option<Vec<u8>>,
+}
+
+/// A followed file and the records read from it.
+struct FollowedFile {
+    path: PathBuf,
+    /// Device and inode, to detect the path being replaced.
+    id: (u64, u64),
+    file: tokio::fs::File,
+    reader: RecordReader,
+    /// Bytes of the records read so far.
+    consumed: u64,
+    /// Packets ending at or before this offset are skipped.
+    skip_until: u64,
+}
+
+impl FollowedFile {
+    async fn open(path: PathBuf, skip_existing: bool) -> Result<Self, String> {
+        let file = tokio::fs::File::open(&path)
+            .await
+            .map_err(|err| format!("failed to open {}: {}", path.display(), err))?;
+        let meta = file
+            .metadata()
+            .await
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+
+        Ok(FollowedFile {
+            path,
+            id: (meta.dev(), meta.ino()),
+            file,
+            reader: RecordReader::new(),
+            consumed: 0,
+            skip_until: if skip_existing { meta.len() } else { 0 },
+        })
+    }
+
+    /// Whether a newer file exists, or the path has been replaced or truncated.
+    async fn is_rotated(&self, newest: &Path) -> bool {
+        if newest != self.path {
+            return true;
+        }
+
+        match tokio::fs::metadata(&self.path).await {
+            Ok(meta) => (meta.dev(), meta.ino()) != self.id || meta.len() < self.consumed,
+            Err(_) => false,
+        }
+    }
+
+    /// Write the complete records added to the file since the last call to tshark.
+    /// Returns false if tshark no longer reads its input.
+    async fn forward(
+        &mut self,
+        stdin: &mut ChildStdin,
+        stream: &mut FollowedStream,
+    ) -> Result<bool, String> {
+        let mut chunk = vec![0; 1 << 16];
+
+        loop {
+            let len = self
+                .file
+                .read(&mut chunk)
+                .await
+                .map_err(|err| format!("failed to read {}: {}", self.path.display(), err))?;
+            if len == 0 {
+                return Ok(true);
+            }
+            self.reader.push(&chunk[..len]);
+
+            while let Some(record) = self
+                .reader
+                .next_record()
+                .map_err(|err| format!("failed to read {}: {}", self.path.display(), err))?
+            {
+                self.consumed += record.data.len() as u64;
+
+                let write = match record.kind {
+                    RecordKind::FileHeader | RecordKind::SectionHeader => {
+                        let format = self.reader.format();
+                        if stream.format.is_some_and(|f| Some(f) != format) {
+                            return Err(format!(
+                                "cannot follow {}: format differs from previous files",
+                                self.path.display()
+                            ));
+                        }
+                        stream.format = format;
+                        record.kind == RecordKind::SectionHeader
+                            || stream.add_pcap_header(&record.data, &self.path)?
+                    }
+                    RecordKind::Packet => self.consumed > self.skip_until,
+                    RecordKind::Other => true,
+                };
+
+                if write && stdin.write_all(&record.data).await.is_err() {
+                    return Ok(false);
+                }
+            }
+        }
+    }
+}
+
+impl FollowedStream {
+    /// Returns whether the pcap file header is the first of the stream and must be
+    /// written. Later files must have the same byte order, timestamp resolution and link
+    /// type, as their headers are left out.
+    fn add_pcap_header(&mut self, header: &[u8], path: &Path) -> Result<bool, String> {
+        match &self.pcap_header {
+            None => {
+                self.pcap_header = Some(header.to_vec());
+                Ok(true)
+            }
+            Some(first) if first[0..4] == header[0..4] && first[20..24] == header[20..24] => {
+                Ok(false)
+            }
+            Some(_) => Err(format!(
+                "cannot follow {}: link type differs from previous files",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// Expand directories to the files they contain and glob patterns to the files matching
+/// them.
+pub fn expand_paths(patterns: &[String]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths = Vec::new();
+
+    for pattern in patterns {
+        let files = find_files(pattern)?;
+        if files.is_empty() {
+            return Err(format!("no capture files found for {}", pattern).into());
+        }
+        paths.extend(files);
+    }
+
+    Ok(paths)
+}
+
+/// Whether a network interface is an 802.11 interface in monitor mode, by its hardware
+/// type. None if the type is unknown, e.g. for an interface given by its number.
+pub fn is_monitor_interface(interface: &str) -> Option<bool> {
+    let hardware_type = fs::read_to_string(format!("/sys/class/net/{}/type", interface)).ok()?;
+    // ARPHRD_IEEE80211, ARPHRD_IEEE80211_PRISM and ARPHRD_IEEE80211_RADIOTAP.
+    Some(matches!(hardware_type.trim(), "801" | "802" | "803"))
+}
+
+/// Whether the path is standard input or a named pipe, which can only be read once.
+pub fn is_stream(path: &str) -> bool {
+    path == "-" || fs::metadata(path).is_ok_and(|meta| meta.file_type().is_fifo())
+}
+
+/// Files of a directory, a file, or files matching a glob pattern, sorted by name.
+fn find_files(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let path = Path::new(pattern);
+
+    let mut files = if path.is_dir() {
+        fs::read_dir(path)
+            .map_err(|err| format!("failed to read directory {}: {}", pattern, err))?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect::<Vec<_>>()
+    } else if path.exists() {
+        vec![path.to_path_buf()]
+    } else {
+        glob::glob(pattern)
+            .map_err(|err| format!("invalid pattern {}: {}", pattern, err))?
+            .filter_map(|entry| entry.ok())
+            .filter(|path| path.is_file())
+            .collect()
+    };
+
+    files.sort();
+    Ok(files)
+}
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::SystemTime;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+use crate::capture::{Burst, FlowKey};
+
+/// Bursts inserted per transaction.
+const BATCH_SIZE: usize = 1000;
+
+/// Seconds after which pending bursts are committed even if the batch is not full.
+const COMMIT_INTERVAL: f64 = 1.0;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    started REAL NOT NULL,
+    ended REAL,
+    command_line TEXT NOT NULL,
+    options TEXT,
+    tshark_args TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS flows (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    src TEXT NOT NULL,
+    dst TEXT NOT NULL,
+    src_port INTEGER NOT NULL,
+    dst_port INTEGER NOT NULL,
+    probe TEXT,
+    interface TEXT
+);
+CREATE TABLE IF NOT EXISTS bursts (
+    id INTEGER PRIMARY KEY,
+    flow_id INTEGER NOT NULL REFERENCES flows (id),
+    start REAL NOT NULL,
+    end REAL NOT NULL,
+    num_packets INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    duration REAL NOT NULL,
+    rate REAL,
+    wlan_bssid TEXT,
+    wlan_transmitter TEXT,
+    wlan_receiver TEXT,
+    wlan_direction TEXT,
+    wlan_mean_signal REAL,
+    wlan_min_signal INTEGER,
+    wlan_dominant_mcs INTEGER,
+    wlan_mean_data_rate REAL,
+    wlan_channel INTEGER,
+    wlan_retry_fraction REAL,
+    wlan_num_estimated INTEGER,
+    wlan_estimated_size INTEGER,
+    wlan_raw_size INTEGER
+);
+CREATE INDEX IF NOT EXISTS flows_run_id ON flows (run_id);
+CREATE INDEX IF NOT EXISTS bursts_flow_id ON bursts (flow_id);
+";
+
+/// Inserts bursts into an SQLite database, with a row per run in runs, per flow of a run
+/// in flows, and per burst in bursts.
+///
+/// The database is created if it does not exist, and each run is added to the existing
+/// tables. Bursts are committed in batches, so the database can be queried while
+/// capturing.
+pub struct SqliteWriter {
+    path: String,
+    conn: Option<Connection>,
+    run_id: i64,
+}
+
+impl SqliteWriter {
+    /// Open the database at path and record the run with its command line, its effective
+    /// options, including defaults and those read from a configuration file, and the
+    /// arguments passed to tshark.
+    pub fn open(
+        path: &str,
+        command_line: &[String],
+        options: &str,
+        tshark_args: &[String],
+    ) -> Result<Self, Box<dyn Error>> {
+        let open = || -> rusqlite::Result<(Connection, i64)> {
+            let conn = Connection::open(path)?;
+            // Readers do not block inserts, nor inserts readers.
+            conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))
+                .optional()?;
+            conn.execute_batch(SCHEMA)?;
+            add_missing_columns(&conn)?;
+            conn.execute(
+                "INSERT INTO runs (started, command_line, options, tshark_args)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    now(),
+                    serde_json::to_string(command_line).unwrap(),
+                    options,
+                    serde_json::to_string(tshark_args).unwrap(),
+                ],
+            )?;
+            let run_id = conn.last_insert_rowid();
+            Ok((conn, run_id))
+        };
+
+        let (conn, run_id) =
+            open().map_err(|err| format!("failed to open database {}: {}", path, err))?;
+
+        Ok(SqliteWriter {
+            path: path.to_string(),
+            conn: Some(conn),
+            run_id,
+        })
+    }
+
+    pub async fn start(&mut self) -> (mpsc::Sender<Burst>, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::channel::<Burst>(100);
+
+        let mut run = Run {
+            conn: self.conn.take().expect("database already started"),
+            id: self.run_id,
+            flows: HashMap::new(),
+        };
+        let path = self.path.clone();
+
+        let handle = tokio::spawn(async move {
+            let mut batch = Vec::new();
+            let mut ticker = interval(Duration::from_secs_f64(COMMIT_INTERVAL));
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            let mut result = Ok(());
+            loop {
+                let ready = tokio::select! {
+                    burst = rx.recv() => match burst {
+                        Some(burst) => {
+                            batch.push(burst);
+                            batch.len() >= BATCH_SIZE
+                        },
+                        None => break,
+                    },
+                    _ = ticker.tick() => !batch.is_empty(),
+                };
+
+                if ready {
+                    let bursts = std::mem::take(&mut batch);
+                    (run, result) = run.blocking(move |run| run.insert(&bursts)).await;
+                    if result.is_err() {
+                        break;
+                    }
+                }
+            }
+
+            if result.is_ok() {
+                (_, result) = run
+                    .blocking(move |run| {
+                        run.insert(&batch)?;
+                        run.conn
+                            .execute(
+                                "UPDATE runs SET ended = ?1 WHERE id = ?2",
+                                params![now(), run.id],
+                            )
+                            .map(|_| ())
+                    })
+                    .await;
+            }
+
+            if let Err(err) = result {
+                eprintln!("Failed to insert bursts into {}: {}", path, err);
+            }
+        });
+
+        (tx, handle)
+    }
+}
+
+/// A run being inserted, with the IDs of its flows inserted so far.
+struct Run {
+    conn: Connection,
+    id: i64,
+    flows: HashMap<FlowKey, i64>,
+}
+
+impl Run {
+    /// Call f on a thread where blocking is allowed, as SQLite blocks on disk I/O.
+    async fn blocking<T: Send + 'static>(
+        mut self,
+        f: impl FnOnce(&mut Run) -> T + Send + 'static,
+    ) -> (Self, T) {
+        tokio::task::spawn_blocking(move || {
+            let result = f(&mut self);
+            (self, result)
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Insert bursts in a single transaction, adding their flows if they are new. The
+    /// writer stops on an error, so flows are cached before the transaction is committed.
+    fn insert(&mut self, bursts: &[Burst]) -> rusqlite::Result<()> {
+        if bursts.is_empty() {
+            return Ok(());
+        }
+
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert_flow = tx.prepare_cached(
+                "INSERT INTO flows (run_id, src, dst, src_port, dst_port, probe, interface)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            let mut insert_burst = tx.prepare_cached(
+                "INSERT INTO bursts (
+                     flow_id, start, end, num_packets, size, duration, rate, wlan_bssid,
+                     wlan_transmitter, wlan_receiver, wlan_direction, wlan_mean_signal,
+                     wlan_min_signal, wlan_dominant_mcs, wlan_mean_data_rate, wlan_channel,
+                     wlan_retry_fraction, wlan_num_estimated, wlan_estimated_size, wlan_raw_size
+                 ) VALUES (
+                     ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18,
+                     ?19, ?20
+                 )",
+            )?;
+
+            for burst in bursts {
+                let key = burst.flow_key();
+                let flow_id = match self.flows.get(&key) {
+                    Some(&id) => id,
+                    None => {
+                        insert_flow
+                            .execute(params![self.id, key.0, key.1, key.2, key.3, key.4, key.5])?;
+                        let id = tx.last_insert_rowid();
+                        self.flows.insert(key, id);
+                        id
+                    }
+                };
+
+                let wlan = burst.wlan.as_ref();
+                insert_burst.execute(params![
+                    flow_id,
+                    burst.start,
+                    burst.end,
+                    burst.num_packets,
+                    burst.size,
+                    burst.duration(),
+                    burst.rate(),
+                    wlan.and_then(|w| w.bssid.as_deref()),
+                    wlan.map(|w| w.transmitter.as_str()),
+                    wlan.map(|w| w.receiver.as_str()),
+                    wlan.and_then(|w| w.direction).map(|d| d.to_string()),
+                    wlan.and_then(|w| w.radio.mean_signal),
+                    wlan.and_then(|w| w.radio.min_signal),
+                    wlan.and_then(|w| w.radio.dominant_mcs),
+                    wlan.and_then(|w| w.radio.mean_data_rate),
+                    wlan.and_then(|w| w.radio.channel),
+                    wlan.map(|w| w.radio.retry_fraction),
+                    wlan.map(|w| w.num_estimated),
+                    wlan.map(|w| w.estimated_size),
+                    wlan.map(|w| w.raw_size),
+                ])?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+}
+
+/// Add columns to the tables of a database created before they existed. The duration
+/// and rate of existing bursts are computed.
+fn add_missing_columns(conn: &Connection) -> rusqlite::Result<()> {
+    if conn.prepare("SELECT options FROM runs").is_err() {
+        conn.execute_batch("ALTER TABLE runs ADD COLUMN options TEXT;")?;
+    }
+    if conn.prepare("SELECT duration FROM bursts").is_err() {
+        conn.execute_batch(
+            "ALTER TABLE bursts ADD COLUMN duration REAL;
+             ALTER TABLE bursts ADD COLUMN rate REAL;
+             UPDATE bursts SET
+                 duration = end - start,
+                 rate = CASE WHEN end > start THEN size * 8.0 / (end - start) END;",
+        )?;
+    }
+    Ok(())
+}
+
+fn now() -> f64 {
+    SystemTime::UNIX_EPOCH
+        .elapsed()
+        .unwrap_or_default()
+        .as_secs_f64()
+}
@@ -0,0 +1,650 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, IsTerminal};
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use nix::sys::signal::{raise, Signal};
+use nix::unistd::{close, dup, dup2};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Sparkline, Table, TableState};
+use ratatui::Frame;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Instant};
+
+use crate::capture::{Burst, FlowKey};
+use crate::output::BurstFilter;
+
+/// Seconds of capture time over which throughput is averaged.
+const RATE_WINDOW: f64 = 10.0;
+
+/// Seconds of capture time shown in the throughput sparkline.
+const HISTORY_SECONDS: usize = 120;
+
+/// Number of recent bursts kept per flow.
+const RECENT_BURSTS: usize = 100;
+
+/// Number of bursts received while paused that are kept. Older ones are dropped.
+const PAUSED_BURSTS: usize = 100_000;
+
+/// Seconds of capture time without bursts after which a flow is no longer shown.
+const FLOW_EXPIRY: f64 = 300.0;
+
+/// Characters of the per-flow sparklines, from smallest to largest burst.
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Milliseconds between redraws of the dashboard.
+const REFRESH_INTERVAL: u64 = 250;
+
+/// Number of messages written to standard error that are kept while the dashboard is shown.
+const MESSAGES: usize = 100;
+
+/// Messages shown below the bursts.
+const SHOWN_MESSAGES: usize = 3;
+
+const STDERR: RawFd = 2;
+
+/// Live terminal dashboard of the busiest flows and their recent bursts.
+pub struct Dashboard {
+    filter: BurstFilter,
+}
+
+impl Dashboard {
+    pub fn new(filter: BurstFilter) -> Self {
+        Dashboard { filter }
+    }
+
+    /// Takes over the terminal until the user quits. Quitting interrupts the capture, and
+    /// the dashboard stays open after the capture ends until the user quits.
+    ///
+    /// Messages written to standard error by BurstShark and tshark while the dashboard is
+    /// shown are shown in it, and printed once it is closed.
+    pub async fn start(&mut self) -> (mpsc::Sender<Burst>, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::channel::<Burst>(100);
+        let filter = self.filter.clone();
+
+        let handle = tokio::spawn(async move {
+            let mut terminal = ratatui::init();
+            let (saved_stderr, mut message_rx) = match capture_stderr() {
+                Some((saved, message_rx)) => (Some(saved), message_rx),
+                None => (None, mpsc::channel(1).1),
+            };
+            let mut state = State::default();
+            let mut key_rx = read_keys();
+            let mut refresh = interval(Duration::from_millis(REFRESH_INTERVAL));
+            let mut capturing = true;
+
+            loop {
+                tokio::select! {
+                    burst = rx.recv(), if capturing => {
+                        match burst {
+                            Some(burst) if filter.matches(&burst) => state.add_burst(burst),
+                            Some(_) => {},
+                            None if state.quitting => break,
+                            None => {
+                                capturing = false;
+                                state.finish();
+                            },
+                        }
+                        continue;
+                    },
+                    Some(message) = message_rx.recv() => state.add_message(message),
+                    Some(event) = key_rx.recv() => {
+                        if !state.handle_event(event) {
+                            if !capturing {
+                                break;
+                            }
+                            // Stop the capture like an interrupt from the terminal, which
+                            // is not delivered in raw mode.
+                            raise(Signal::SIGINT).ok();
+                            state.quitting = true;
+                        }
+                    },
+                    _ = refresh.tick() => {},
+                }
+
+                terminal.draw(|frame| state.draw(frame)).unwrap();
+            }
+
+            ratatui::restore();
+            if let Some(saved) = saved_stderr {
+                restore_stderr(saved);
+                while let Ok(message) = message_rx.try_recv() {
+                    state.add_message(message);
+                }
+                for message in &state.messages {
+                    eprintln!("{}", message);
+                }
+            }
+        });
+
+        (tx, handle)
+    }
+}
+
+/// Redirect standard error, which is inherited by tshark, to lines read on a blocking
+/// thread, so that messages do not draw over the dashboard. Returns the original standard
+/// error, or None if it is not a terminal or cannot be redirected.
+fn capture_stderr() -> Option<(RawFd, mpsc::Receiver<String>)> {
+    if !std::io::stderr().is_terminal() {
+        return None;
+    }
+
+    let (reader, writer) = std::io::pipe().ok()?;
+    let saved = dup(STDERR).ok()?;
+    if dup2(writer.as_raw_fd(), STDERR).is_err() {
+        close(saved).ok();
+        return None;
+    }
+    drop(writer);
+
+    // Restore standard error before a panic message is printed.
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        dup2(saved, STDERR).ok();
+        hook(info);
+    }));
+
+    let (tx, rx) = mpsc::channel(100);
+    std::thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if tx.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    Some((saved, rx))
+}
+
+fn restore_stderr(saved: RawFd) {
+    dup2(saved, STDERR).ok();
+    close(saved).ok();
+}
+
+/// Read terminal events on a blocking thread until the receiver is dropped.
+fn read_keys() -> mpsc::Receiver<Event> {
+    let (tx, rx) = mpsc::channel(100);
+
+    std::thread::spawn(move || {
+        while !tx.is_closed() {
+            if !event::poll(Duration::from_millis(REFRESH_INTERVAL)).unwrap_or(false) {
+                continue;
+            }
+            match event::read() {
+                Ok(event) => {
+                    if tx.blocking_send(event).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    rx
+}
+
+struct FlowStats {
+    num_bursts: u64,
+    bytes: u64,
+    recent: VecDeque<Burst>,
+}
+
+#[derive(Default)]
+struct State {
+    flows: HashMap<FlowKey, FlowStats>,
+    num_bursts: u64,
+    bytes: u64,
+    /// Bytes of bursts by the second of capture time they ended in.
+    seconds: VecDeque<(i64, u64)>,
+    /// Latest burst end and when it was received. Capture time advances with program
+    /// time between bursts.
+    last_burst: Option<(f64, Instant)>,
+    /// Bursts received while paused, added when resumed.
+    paused: Option<(VecDeque<Burst>, Instant)>,
+    /// Bursts received while paused that were dropped, as too many were received.
+    num_dropped: u64,
+    /// Capture time when the capture ended.
+    finished: Option<f64>,
+    quitting: bool,
+    /// Latest messages written to standard error.
+    messages: VecDeque<String>,
+    /// Only flows with an address, port, probe or interface containing the text are shown.
+    flow_filter: String,
+    editing_filter: bool,
+    selected: Option<FlowKey>,
+}
+
+impl State {
+    fn add_burst(&mut self, burst: Burst) {
+        if let Some((pending, _)) = self.paused.as_mut() {
+            if pending.len() == PAUSED_BURSTS {
+                pending.pop_front();
+                self.num_dropped += 1;
+            }
+            pending.push_back(burst);
+            return;
+        }
+
+        self.num_bursts += 1;
+        self.bytes += burst.size as u64;
+
+        let second = burst.end.floor() as i64;
+        match self.seconds.iter_mut().rev().find(|(s, _)| *s == second) {
+            Some((_, bytes)) => *bytes += burst.size as u64,
+            None => {
+                let at = self.seconds.partition_point(|(s, _)| *s < second);
+                self.seconds.insert(at, (second, burst.size as u64));
+            }
+        }
+
+        if self.last_burst.is_none_or(|(end, _)| burst.end > end) {
+            self.last_burst = Some((burst.end, Instant::now()));
+        }
+
+        let key = burst.flow_key();
+        let flow = self.flows.entry(key).or_insert_with(|| FlowStats {
+            num_bursts: 0,
+            bytes: 0,
+            recent: VecDeque::new(),
+        });
+        flow.num_bursts += 1;
+        flow.bytes += burst.size as u64;
+        if flow.recent.len() == RECENT_BURSTS {
+            flow.recent.pop_front();
+        }
+        flow.recent.push_back(burst);
+
+        // Forget what is too old to be shown.
+        let now = self.now();
+        let oldest = now.floor() as i64 - HISTORY_SECONDS as i64;
+        while self.seconds.front().is_some_and(|(s, _)| *s < oldest) {
+            self.seconds.pop_front();
+        }
+        self.flows.retain(|_, flow| {
+            flow.recent
+                .back()
+                .is_some_and(|b| now - b.end <= FLOW_EXPIRY)
+        });
+    }
+
+    fn add_message(&mut self, message: String) {
+        if self.messages.len() == MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+    }
+
+    fn finish(&mut self) {
+        self.resume();
+        self.finished = Some(self.now());
+    }
+
+    fn resume(&mut self) {
+        if let Some((pending, _)) = self.paused.take() {
+            for burst in pending {
+                self.add_burst(burst);
+            }
+        }
+    }
+
+    /// Current capture time.
+    fn now(&self) -> f64 {
+        if let Some(finished) = self.finished {
+            return finished;
+        }
+        let Some((end, received)) = self.last_burst else {
+            return 0.0;
+        };
+        let until = self
+            .paused
+            .as_ref()
+            .map_or(Instant::now(), |(_, since)| *since);
+        end + until.saturating_duration_since(received).as_secs_f64()
+    }
+
+    /// Handle a terminal event. Returns false if the user quits.
+    fn handle_event(&mut self, event: Event) -> bool {
+        let Event::Key(key) = event else {
+            return true;
+        };
+        if key.kind == KeyEventKind::Release {
+            return true;
+        }
+
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return false;
+        }
+
+        if self.editing_filter {
+            match key.code {
+                KeyCode::Enter | KeyCode::Esc => self.editing_filter = false,
+                KeyCode::Backspace => {
+                    self.flow_filter.pop();
+                }
+                KeyCode::Char(c) => self.flow_filter.push(c),
+                _ => {}
+            }
+            return true;
+        }
+
+        match key.code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('p') | KeyCode::Char(' ') => match self.paused {
+                Some(_) => self.resume(),
+                None if self.finished.is_none() => {
+                    self.paused = Some((VecDeque::new(), Instant::now()));
+                }
+                None => {}
+            },
+            KeyCode::Char('/') => self.editing_filter = true,
+            KeyCode::Esc => self.flow_filter.clear(),
+            KeyCode::Up => self.select(-1),
+            KeyCode::Down => self.select(1),
+            _ => {}
+        }
+
+        true
+    }
+
+    /// Move the selection by offset rows in the flow table.
+    fn select(&mut self, offset: isize) {
+        let flows = self.shown_flows();
+        if flows.is_empty() {
+            return;
+        }
+
+        let index = match self.selected_index(&flows) {
+            Some(index) => index.saturating_add_signed(offset).min(flows.len() - 1),
+            None => 0,
+        };
+        self.selected = Some(flows[index].0.clone());
+    }
+
+    fn selected_index(&self, flows: &[(&FlowKey, &FlowStats, f64)]) -> Option<usize> {
+        let selected = self.selected.as_ref()?;
+        flows.iter().position(|(key, ..)| *key == selected)
+    }
+
+    /// Flows matching the filter with their throughput in bits/s, busiest first.
+    fn shown_flows(&self) -> Vec<(&FlowKey, &FlowStats, f64)> {
+        let now = self.now();
+        let mut flows: Vec<_> = self
+            .flows
+            .iter()
+            .filter(|(key, _)| self.flow_matches(key))
+            .map(|(key, flow)| {
+                let recent_bytes: u64 = flow
+                    .recent
+                    .iter()
+                    .rev()
+                    .take_while(|b| now - b.end <= RATE_WINDOW)
+                    .map(|b| b.size as u64)
+                    .sum();
+                (key, flow, recent_bytes as f64 * 8.0 / RATE_WINDOW)
+            })
+            .collect();
+
+        flows.sort_by(|a, b| b.2.total_cmp(&a.2).then(b.1.bytes.cmp(&a.1.bytes)));
+        flows
+    }
+
+    fn flow_matches(&self, key: &FlowKey) -> bool {
+        let filter = self.flow_filter.as_str();
+        filter.is_empty()
+            || key.0.contains(filter)
+            || key.1.contains(filter)
+            || key.2.to_string().contains(filter)
+            || key.3.to_string().contains(filter)
+            || key.4.as_ref().is_some_and(|p| p.contains(filter))
+            || key.5.as_ref().is_some_and(|i| i.contains(filter))
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let shown_messages = self.messages.len().min(SHOWN_MESSAGES);
+        let messages_height = match shown_messages {
+            0 => 0,
+            n => n as u16 + 2,
+        };
+        let [summary_area, throughput_area, flows_area, bursts_area, messages_area, help_area] =
+            Layout::vertical([
+                Constraint::Length(1),
+                Constraint::Length(5),
+                Constraint::Min(6),
+                Constraint::Length(10),
+                Constraint::Length(messages_height),
+                Constraint::Length(1),
+            ])
+            .areas(frame.area());
+
+        let flows = self.shown_flows();
+        let now = self.now();
+
+        let rate: u64 = self
+            .seconds
+            .iter()
+            .filter(|(s, _)| now - (*s as f64) <= RATE_WINDOW)
+            .map(|(_, bytes)| bytes)
+            .sum();
+        let mut summary = format!(
+            " BurstShark  {} flows  {} bursts  {}  {}",
+            self.flows.len(),
+            self.num_bursts,
+            format_bytes(self.bytes),
+            format_rate(rate as f64 * 8.0 / RATE_WINDOW),
+        );
+        if self.num_dropped > 0 {
+            summary.push_str(&format!("  {} dropped while paused", self.num_dropped));
+        }
+        if self.paused.is_some() {
+            summary.push_str("  [paused]");
+        }
+        if self.quitting {
+            summary.push_str("  [stopping]");
+        } else if self.finished.is_some() {
+            summary.push_str("  [capture ended]");
+        }
+        frame.render_widget(Paragraph::new(summary).bold(), summary_area);
+
+        self.draw_throughput(frame, throughput_area, now);
+        self.draw_flows(frame, flows_area, &flows);
+        self.draw_bursts(frame, bursts_area, &flows);
+        if shown_messages > 0 {
+            let messages: Vec<Line> = self
+                .messages
+                .range(self.messages.len() - shown_messages..)
+                .map(|message| Line::from(message.as_str()))
+                .collect();
+            frame.render_widget(
+                Paragraph::new(messages).block(Block::bordered().title("Messages")),
+                messages_area,
+            );
+        }
+
+        let help = match self.editing_filter {
+            true => format!(" Filter flows: {}_  (Enter to apply)", self.flow_filter),
+            false if !self.flow_filter.is_empty() => format!(
+                " q quit  p pause  / filter ({})  Esc clear filter  ↑↓ select flow",
+                self.flow_filter
+            ),
+            false => " q quit  p pause  / filter  ↑↓ select flow".to_string(),
+        };
+        frame.render_widget(Paragraph::new(help).reversed(), help_area);
+    }
+
+    /// Bytes per second of capture time, oldest on the left.
+    fn draw_throughput(&self, frame: &mut Frame, area: Rect, now: f64) {
+        let width = (area.width.saturating_sub(2) as usize).min(HISTORY_SECONDS);
+        let last = now.floor() as i64;
+        let data: Vec<u64> = (last - width as i64 + 1..=last)
+            .map(|second| {
+                self.seconds
+                    .iter()
+                    .find(|(s, _)| *s == second)
+                    .map_or(0, |(_, bytes)| *bytes)
+            })
+            .collect();
+        let max = data.iter().copied().max().unwrap_or(0);
+
+        let title = format!(
+            "Throughput, last {} s (peak {})",
+            width,
+            format_rate(max as f64 * 8.0)
+        );
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(title))
+                .data(&data),
+            area,
+        );
+    }
+
+    fn draw_flows(&self, frame: &mut Frame, area: Rect, flows: &[(&FlowKey, &FlowStats, f64)]) {
+        let tags = |key: &FlowKey| -> String {
+            let tags: Vec<&str> = [&key.4, &key.5]
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect();
+            tags.join(" ")
+        };
+        let width = |header: &str, values: &mut dyn Iterator<Item = usize>| -> u16 {
+            values.max().unwrap_or(0).max(header.len()) as u16
+        };
+
+        let mut header = vec!["Source", "Port", "Destination", "Port"];
+        let mut widths = vec![
+            width("Source", &mut flows.iter().map(|(key, ..)| key.0.len())),
+            5,
+            width(
+                "Destination",
+                &mut flows.iter().map(|(key, ..)| key.1.len()),
+            ),
+            5,
+        ];
+        let tagged = flows
+            .iter()
+            .any(|(key, ..)| key.4.is_some() || key.5.is_some());
+        if tagged {
+            header.push("Probe/interface");
+            widths.push(width(
+                "Probe/interface",
+                &mut flows.iter().map(|(key, ..)| tags(key).len()),
+            ));
+        }
+        header.extend(["Bursts", "Bytes", "Rate", "Recent burst sizes"]);
+        widths.extend([7, 9, 12]);
+
+        // The sparklines fill the rest of the row, within the borders and column spacing.
+        let used = widths.iter().sum::<u16>() + widths.len() as u16 + 2;
+        let spark_width = area.width.saturating_sub(used) as usize;
+        widths.push(spark_width as u16);
+
+        let rows = flows.iter().map(|(key, flow, rate)| {
+            let mut cells = vec![
+                key.0.clone(),
+                key.2.to_string(),
+                key.1.clone(),
+                key.3.to_string(),
+            ];
+            if tagged {
+                cells.push(tags(key));
+            }
+            cells.extend([
+                flow.num_bursts.to_string(),
+                format_bytes(flow.bytes),
+                format_rate(*rate),
+                spark(&flow.recent, spark_width),
+            ]);
+            Row::new(cells)
+        });
+
+        let table = Table::new(rows, widths.into_iter().map(Constraint::Length))
+            .header(Row::new(header).add_modifier(Modifier::BOLD))
+            .block(Block::bordered().title(format!("Flows ({})", flows.len())))
+            .row_highlight_style(Style::new().reversed());
+
+        let mut table_state = TableState::default().with_selected(self.selected_index(flows));
+        frame.render_stateful_widget(table, area, &mut table_state);
+    }
+
+    fn draw_bursts(&self, frame: &mut Frame, area: Rect, flows: &[(&FlowKey, &FlowStats, f64)]) {
+        let Some(index) = self.selected_index(flows) else {
+            let hint = Paragraph::new("Select a flow with ↑↓ to show its recent bursts.")
+                .block(Block::bordered().title("Bursts"));
+            frame.render_widget(hint, area);
+            return;
+        };
+        let (key, flow, _) = flows[index];
+
+        let rows = flow
+            .recent
+            .iter()
+            .rev()
+            .take(area.height.saturating_sub(3) as usize)
+            .map(|burst| {
+                Row::new([
+                    format!("{:.6}", burst.start),
+                    format!("{:.6}", burst.end),
+                    format!("{:.3}", burst.duration()),
+                    burst.num_packets.to_string(),
+                    burst.size.to_string(),
+                ])
+            });
+
+        let title = Line::from(format!(
+            "Bursts of {}:{} → {}:{}",
+            key.0, key.2, key.1, key.3
+        ));
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(18),
+                Constraint::Length(18),
+                Constraint::Length(9),
+                Constraint::Length(8),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(
+            Row::new(["Start", "End", "Duration", "Packets", "Size"]).add_modifier(Modifier::BOLD),
+        )
+        .block(Block::bordered().title(title));
+
+        frame.render_widget(table, area);
+    }
+}
+
+/// Sizes of up to width of the most recent bursts, relative to the largest of them.
+fn spark(bursts: &VecDeque<Burst>, width: usize) -> String {
+    let bursts = bursts.range(bursts.len().saturating_sub(width)..);
+    let max = bursts.clone().map(|b| b.size).max().unwrap_or(0).max(1) as u64;
+    bursts
+        .map(|b| BARS[((b.size as u64 * (BARS.len() as u64 - 1)) / max) as usize])
+        .collect()
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..1_000 => format!("{} B", bytes),
+        1_000..1_000_000 => format!("{:.1} kB", bytes as f64 / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.1} MB", bytes as f64 / 1e6),
+        _ => format!("{:.1} GB", bytes as f64 / 1e9),
+    }
+}
+
+fn format_rate(bits_per_second: f64) -> String {
+    match bits_per_second {
+        r if r < 1e3 => format!("{:.0} bit/s", r),
+        r if r < 1e6 => format!("{:.1} kbit/s", r / 1e3),
+        r if r < 1e9 => format!("{:.1} Mbit/s", r / 1e6),
+        r => format!("{:.1} Gbit/s", r / 1e9),
+    }
+}